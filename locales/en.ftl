@@ -2,8 +2,17 @@ hello = Hello
 welcome = Welcome to our application
 help-command = Help Command
 version-command = Version
-init-command = Initialize Command
-set-key-command = Arguments are <key>, Add encryption key
+init-command = Arguments are [--cipher <suite>], Initialize Command
+set-key-command = Arguments are <key|-> [--raw] [--name <name>] [--m-cost|--t-cost|--p-cost <n>], Add encryption key
+keygen-command = Arguments are [--name <name>], Generate a random 256-bit key
+export-key-command = Arguments are <file>, Export the key to a key file
+unlock-command = Arguments are <file> or --identity <file>, Import an exported key file or unwrap the key shared with your identity; without arguments, ask for the passphrase of a protected key and keep it unlocked for [--timeout <minutes>]
+lock-command = Arguments are [--name <name>], Restore encrypted content of filtered files in the working tree, then remove the key and retired keys from .git/cryptx/keys (only forget the session of a protected key)
+protect-key-command = Arguments are [--name <name>] [--remove], Protect the stored key with a passphrase
+gen-identity-command = Arguments are <file>, Generate an X25519 identity for receiving the key
+add-recipient-command = Arguments are <public-key>, Share the key with the owner of an X25519 public key
+rotate-key-command = Arguments are [<key|->] [--raw] [--name <name>] [--remove-recipient <fingerprint>], Replace the key and re-encrypt tracked files, keeping the old key for history; recipients are re-wrapped with the new key
+rebind-command = Arguments are [--name <name>], Re-encrypt staged files bound to another path (after git mv) for their current path
 rm-key-command = Remove Key Command
 status-command = Status Command
 clean-command = Clean
@@ -25,8 +34,13 @@ create-gitattributes-error = Error creating .gitattributes
 create-gitattributes-success = Successfully created .gitattributes
 configure-git-filter-error = Error configuring git filter
 init-success = Initialization successful
+cipher-suite-label = Cipher suite
+cipher-suite-error = Unsupported cipher suite, choose one of
+init-config-staged = Cipher suite staged in .git-cryptx/config.toml, commit it so every clone encrypts files the same way
 ensure-git-cryptx-dir-error = Error ensuring git-cryptx directory
 key-not-configured = Key not configured
+key-permissions-warning = Key directory or file is accessible to other users, restrict it with chmod go-rwx
+key-env-error = Invalid key in environment variable
 gitattributes-not-configured = .gitattributes not configured
 git-filter-not-configured = Git filter not configured
 key-configured = Key configured
@@ -34,9 +48,81 @@ git-filter-configured = Git filter configured
 git-cryptx-filter-configured = git-cryptx Filter Configured
 set-key-length-error = Key cannot be less than 8 characters
 set-key-empty-error = Set Key cannot be empty
+set-key-stdin-error = Key cannot be read from standard input
 set-key-exists-error = Key already exists
 set-key-write-error = Key cannot be written properly
 set-key-success = Key set successfully
+set-key-raw-format-error = Raw key must be 64 hexadecimal characters (256 bits)
+set-key-kdf-error = Key derivation failed
+set-key-cost-error = Invalid key derivation cost
+set-key-kdf-staged = Key derivation parameters staged in .git-cryptx/kdf.toml, commit them so the same passphrase gives the same key in every clone
+set-key-legacy = Passphrase decrypts the files in HEAD with the legacy SHA-256 key derivation, keeping it
+keygen-success = Random key generated
+keygen-error = Key generation failed
+key-fingerprint-label = Key fingerprint
+export-key-empty-error = Export file path cannot be empty
+export-key-read-error = Key cannot be read properly
+export-key-exists-error = Export file already exists
+export-key-write-error = Key file cannot be written properly
+export-key-success = Key exported successfully
+unlock-empty-error = Key file path cannot be empty
+unlock-read-error = Key file cannot be read
+unlock-format-error = Invalid key file
+unlock-verify-error = Key cannot decrypt the encrypted files in HEAD
+unlock-unverified = No encrypted file in HEAD, key imported without verification
+unlock-success = Key imported successfully
+unlock-session-success = Key unlocked, minutes until it is locked again
+unlock-checkout-success = Files decrypted in the working tree
+unlock-checkout-error = Encrypted files in the working tree cannot be decrypted
+unlock-timeout-error = Timeout must be a positive number of minutes
+lock-success = Key locked, files restored to encrypted content
+lock-no-session = Key is not unlocked
+lock-modified-error = Files have uncommitted changes, commit or stash them before locking
+lock-error = Locking the key failed
+key-locked-error = Key is protected by a passphrase and locked, run git-cryptx unlock first
+passphrase-prompt = Passphrase
+passphrase-confirm-prompt = Confirm passphrase
+passphrase-read-error = Passphrase cannot be read
+passphrase-mismatch-error = Passphrases do not match
+passphrase-error = Key cannot be unlocked
+protect-key-success = Key is now protected by the passphrase, run git-cryptx unlock before using it
+protect-key-remove-success = Passphrase protection removed
+protect-key-already-error = Key is already protected by a passphrase
+protect-key-not-protected-error = Key is not protected by a passphrase
+protect-key-error = Protecting the key failed
+identity-read-error = Identity file cannot be read
+recipient-not-found-error = No key has been added for this identity
+gen-identity-empty-error = Identity file path cannot be empty
+gen-identity-success = Identity generated, share the public key with a team member who has the key
+public-key-label = Public key
+add-recipient-empty-error = Public key cannot be empty
+add-recipient-format-error = Invalid public key
+add-recipient-exists-error = Recipient already exists
+add-recipient-error = Recipient cannot be added
+add-recipient-success = Recipient added and staged, commit .git-cryptx to share it
+key-name-error = Key name may only contain letters, digits, - and _
+named-filter-hint = Use this key in .gitattributes with
+rotate-key-same-error = New key is the same as the current key
+rotate-key-protected-error = Key is protected by a passphrase, run git-cryptx protect-key --remove before rotating it
+rotate-key-error = Key rotation failed
+rotate-key-success = Key rotated (commit to finish), files re-encrypted and staged
+rotate-key-recipients = Recipients re-wrapped with the new key and staged
+rebind-error = Rebinding files failed
+rebind-success = Files rebound to their current path and staged
+filter-path-error = Missing file path argument
+filter-read-error = Failed to read content from Git
+filter-write-error = Failed to write filtered content
+filter-key-warning = Cannot load key, file left encrypted
+filter-key-error = Cannot load key
+filter-missing-key-error = No key available, refusing to store the file as plaintext (set cryptx.missingKey to passthrough to allow it)
+filter-encrypt-error = Failed to encrypt file
+filter-double-encryption-error = File content is already encrypted with an unknown key, refusing to encrypt it again
+filter-decrypt-error = Failed to decrypt file
+filter-decrypt-warning = Failed to decrypt file, leaving it encrypted (set cryptx.smudge to strict to abort instead)
+filter-path-mismatch-error = Encrypted file is bound to another path, it was moved or swapped with another file (run git-cryptx rebind after git mv)
+filter-path-mismatch-warning = Encrypted file is bound to another path, leaving it encrypted (run git-cryptx rebind after git mv)
+bound-path-label = Bound path
+filter-process-error = Filter process failed
 rm-key-not-exists-error = Key does not exist
 rm-key-remove-error = Key removal exception
 rm-key-success = Key removed successfully
@@ -2,8 +2,17 @@ hello = 你好
 welcome = 欢迎使用我们的应用程序
 help-command = 帮助命令
 version-command = 版本
-init-command = 初始化命令
-set-key-command = 参数为 <key>，添加加密密钥
+init-command = 参数为 [--cipher <suite>]，初始化命令
+set-key-command = 参数为 <key|-> [--raw] [--name <name>] [--m-cost|--t-cost|--p-cost <n>]，添加加密密钥
+keygen-command = 参数为 [--name <name>]，生成 256 位随机密钥
+export-key-command = 参数为 <file>，将密钥导出为密钥文件
+unlock-command = 参数为 <file> 或 --identity <file>，导入导出的密钥文件或解开共享给你的密钥；不带参数时输入受保护密钥的口令，在 [--timeout <minutes>] 分钟内保持解锁
+lock-command = 参数为 [--name <name>]，将工作区中使用过滤器的文件恢复为密文，然后删除 .git/cryptx/keys 中的密钥和轮换保留的旧密钥（受口令保护的密钥只清除会话）
+protect-key-command = 参数为 [--name <name>] [--remove]，使用口令保护保存的密钥
+gen-identity-command = 参数为 <file>，生成用于接收密钥的 X25519 身份
+add-recipient-command = 参数为 <public-key>，将密钥共享给 X25519 公钥的持有者
+rotate-key-command = 参数为 [<key|->] [--raw] [--name <name>] [--remove-recipient <fingerprint>]，更换密钥并重新加密已跟踪的文件，旧密钥保留用于历史提交；接收者改为包装新密钥
+rebind-command = 参数为 [--name <name>]，将暂存区中绑定到其他路径的文件（git mv 之后）重新加密并绑定到当前路径
 rm-key-command = 删除密钥命令
 status-command = 状态命令
 clean-command = 清理
@@ -25,8 +34,13 @@ create-gitattributes-error = 创建.gitattributes错误
 create-gitattributes-success = 成功创建.gitattributes
 configure-git-filter-error = 配置git过滤器错误
 init-success = 初始化成功
+cipher-suite-label = 加密套件
+cipher-suite-error = 不支持的加密套件，可选值为
+init-config-staged = 加密套件已写入并暂存 .git-cryptx/config.toml，提交后各克隆以相同的方式加密文件
 ensure-git-cryptx-dir-error = git-cryptx目录读取错误
 key-not-configured = 密钥未配置
+key-permissions-warning = 其他用户可以访问密钥目录或文件，请使用 chmod go-rwx 限制权限
+key-env-error = 环境变量中的密钥无效
 gitattributes-not-configured = .gitattributes未配置
 git-filter-not-configured = Git过滤器未配置
 key-configured = 密钥已配置
@@ -34,9 +48,81 @@ git-filter-configured = Git过滤器已配置
 git-cryptx-filter-configured = git-cryptx过滤器已配置
 set-key-length-error = 密钥不能小于8位
 set-key-empty-error = 设置密钥不能为空
+set-key-stdin-error = 无法从标准输入读取密钥
 set-key-exists-error = 密钥不允许重复设置
 set-key-write-error = 密钥无法正常写入
 set-key-success = 密钥设置成功
+set-key-raw-format-error = 原始密钥必须是 64 位十六进制字符（256 位）
+set-key-kdf-error = 密钥派生失败
+set-key-cost-error = 密钥派生开销参数无效
+set-key-kdf-staged = 密钥派生参数已写入并暂存 .git-cryptx/kdf.toml，提交后各克隆使用同一口令得到相同的密钥
+set-key-legacy = 口令使用旧版本的 SHA-256 派生方式可以解密 HEAD 中的文件，继续使用旧的派生方式
+keygen-success = 已生成随机密钥
+keygen-error = 密钥生成失败
+key-fingerprint-label = 密钥指纹
+export-key-empty-error = 导出文件路径不能为空
+export-key-read-error = 密钥无法正常读取
+export-key-exists-error = 导出文件已存在
+export-key-write-error = 密钥文件无法正常写入
+export-key-success = 密钥导出成功
+unlock-empty-error = 密钥文件路径不能为空
+unlock-read-error = 密钥文件无法读取
+unlock-format-error = 密钥文件无效
+unlock-verify-error = 密钥无法解密 HEAD 中的加密文件
+unlock-unverified = HEAD 中没有加密文件，密钥未经校验直接导入
+unlock-success = 密钥导入成功
+unlock-session-success = 密钥已解锁，自动锁定前的分钟数
+unlock-checkout-success = 工作区中已解密的文件数
+unlock-checkout-error = 无法解密工作区中的加密文件
+unlock-timeout-error = 有效时间必须是正整数分钟
+lock-success = 密钥已锁定，恢复为密文的文件数
+lock-no-session = 密钥未解锁
+lock-modified-error = 文件有未提交的修改，请先提交或储藏后再锁定
+lock-error = 锁定密钥失败
+key-locked-error = 密钥受口令保护且已锁定，请先执行 git-cryptx unlock
+passphrase-prompt = 口令
+passphrase-confirm-prompt = 确认口令
+passphrase-read-error = 无法读取口令
+passphrase-mismatch-error = 两次输入的口令不一致
+passphrase-error = 无法解锁密钥
+protect-key-success = 密钥已受口令保护，使用前请执行 git-cryptx unlock
+protect-key-remove-success = 已取消口令保护
+protect-key-already-error = 密钥已受口令保护
+protect-key-not-protected-error = 密钥未受口令保护
+protect-key-error = 保护密钥失败
+identity-read-error = 身份文件无法读取
+recipient-not-found-error = 尚未为此身份添加密钥
+gen-identity-empty-error = 身份文件路径不能为空
+gen-identity-success = 身份已生成，请将公钥发送给持有密钥的团队成员
+public-key-label = 公钥
+add-recipient-empty-error = 公钥不能为空
+add-recipient-format-error = 公钥无效
+add-recipient-exists-error = 接收者已存在
+add-recipient-error = 无法添加接收者
+add-recipient-success = 接收者已添加并暂存，提交 .git-cryptx 后即可共享
+key-name-error = 密钥名称只能包含字母、数字、- 和 _
+named-filter-hint = 在 .gitattributes 中使用此密钥
+rotate-key-same-error = 新密钥与当前密钥相同
+rotate-key-protected-error = 密钥受口令保护，轮换前请执行 git-cryptx protect-key --remove
+rotate-key-error = 密钥轮换失败
+rotate-key-success = 密钥已轮换（提交后生效），重新加密并暂存的文件数
+rotate-key-recipients = 改为包装新密钥并暂存的接收者数
+rebind-error = 重新绑定文件失败
+rebind-success = 已重新绑定到当前路径并暂存的文件数
+filter-path-error = 缺少文件路径参数
+filter-read-error = 无法读取 Git 传入的内容
+filter-write-error = 无法写出过滤后的内容
+filter-key-warning = 无法加载密钥，文件保持加密
+filter-key-error = 无法加载密钥
+filter-missing-key-error = 没有可用的密钥，拒绝以明文保存文件（如需允许，请将 cryptx.missingKey 设置为 passthrough）
+filter-encrypt-error = 文件加密失败
+filter-double-encryption-error = 文件内容已被未知密钥加密，拒绝重复加密
+filter-decrypt-error = 文件解密失败
+filter-decrypt-warning = 文件解密失败，保持加密（将 cryptx.smudge 设置为 strict 可改为中止）
+filter-path-mismatch-error = 加密文件绑定的是其他路径，文件被移动或与其他文件交换（git mv 之后请执行 git-cryptx rebind）
+filter-path-mismatch-warning = 加密文件绑定的是其他路径，保持加密状态（git mv 之后请执行 git-cryptx rebind）
+bound-path-label = 绑定路径
+filter-process-error = 过滤进程异常
 rm-key-not-exists-error = 密钥不存在
 rm-key-remove-error = 密钥删除异常
 rm-key-success = 密钥删除成功
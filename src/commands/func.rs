@@ -1,10 +1,19 @@
-use crate::{crypto::Encryptor, util};
+use crate::{
+    crypto::{
+        CipherSuite, Compression, CryptoError, EncryptOptions, Encryptor, Header, Kdf, KdfParams,
+        KeyExport, KeyFile, Keyring, Padding, ProtectedKeyFile, SecretKey,
+    },
+    recipient::{self, Identity, WrappedKey},
+    util,
+};
 use fluent_bundle::{FluentArgs, FluentBundle, FluentResource};
 use std::env;
 use std::fs;
 use std::io::{self, Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use toml::Value;
+use zeroize::Zeroizing;
 
 fn load_website_url() -> String {
     let config_content = include_str!("../../config.toml");
@@ -22,13 +31,23 @@ pub fn handle_command(command_str: &str, parameters: &[String], language: &str)
     match command_str {
         "help" => help(parameters, &bundle, &mut args),
         "version" => version(&bundle),
-        "init" => init(&bundle),
+        "init" => init(parameters, &bundle),
         "set-key" => add_key(parameters, &bundle),
+        "keygen" => keygen(parameters, &bundle),
+        "export-key" => export_key(parameters, &bundle),
+        "unlock" => unlock(parameters, &bundle),
+        "lock" => lock(parameters, &bundle),
+        "protect-key" => protect_key(parameters, &bundle),
+        "gen-identity" => gen_identity(parameters, &bundle),
+        "add-recipient" => add_recipient(parameters, &bundle),
+        "rotate-key" => rotate_key(parameters, &bundle),
+        "rebind" => rebind(parameters, &bundle),
         "rm-key" => remove_key(parameters, &bundle),
         "status" => status(&bundle),
         "clean" => clean(parameters, &bundle),
         "smudge" => smudge(parameters, &bundle),
         "diff" => diff(parameters, &bundle),
+        "filter-process" => filter_process(parameters, &bundle),
         "reset" => reset_files(parameters, &bundle),
         _ => util::log_error("Unknown command"),
     }
@@ -48,7 +67,23 @@ fn help(_parameters: &[String], bundle: &FluentBundle<FluentResource>, args: &mu
     );
 
     // List all commands
-    let commands = vec!["help", "version", "init", "set-key", "rm-key", "status"];
+    let commands = vec![
+        "help",
+        "version",
+        "init",
+        "set-key",
+        "keygen",
+        "export-key",
+        "unlock",
+        "lock",
+        "protect-key",
+        "gen-identity",
+        "add-recipient",
+        "rotate-key",
+        "rebind",
+        "rm-key",
+        "status",
+    ];
     for command in commands {
         let key = format!("{}-command", command);
         let msg = bundle.get_message(&key).expect("Message not found");
@@ -76,13 +111,25 @@ fn version(bundle: &FluentBundle<FluentResource>) {
     );
 }
 
-fn init(bundle: &FluentBundle<FluentResource>) {
+fn init(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
     let website_url = load_website_url();
     let mut errors = vec![];
     let value = util::format_pattern(bundle, "init-command", &mut errors);
 
     println!("{}", value);
 
+    // 加密套件记录在每个加密文件的头部中，之后更换不影响已有文件的解密
+    let suite = match util::flag_value(parameters, "--cipher").map(CipherSuite::from_name) {
+        None => None,
+        Some(Ok(suite)) => Some(suite),
+        Some(Err(_)) => {
+            let names: Vec<&str> = CipherSuite::ALL.iter().map(|s| s.name()).collect();
+            let value = util::format_pattern(bundle, "cipher-suite-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, names.join(", ")));
+            return;
+        }
+    };
+
     let git_root = match util::find_git_root() {
         Some(path) => path,
         None => {
@@ -96,6 +143,7 @@ fn init(bundle: &FluentBundle<FluentResource>) {
         }
     };
 
+    remove_expired_sessions(&git_root);
     let gitattributes_path = git_root.join(".gitattributes");
     let default_config = "example.secret filter=git-cryptx diff=git-cryptx";
 
@@ -159,7 +207,15 @@ fn init(bundle: &FluentBundle<FluentResource>) {
     }
 
     // 配置 Git 过滤器
-    if let Err(e) = util::configure_git_filter(&git_root) {
+    let configured = util::configure_git_filter(&git_root, None).and_then(|_| match suite {
+        Some(suite) => FilterPolicy::write_setting(
+            &git_root,
+            FilterPolicy::CIPHER_CONFIG,
+            toml::Value::String(suite.name().to_string()),
+        ),
+        None => Ok(()),
+    });
+    if let Err(e) = configured {
         let mut errors = vec![];
         util::log_error(
             &util::format_pattern(bundle, "configure-git-filter-error", &mut errors)
@@ -167,6 +223,17 @@ fn init(bundle: &FluentBundle<FluentResource>) {
         );
         return;
     }
+    if let Some(suite) = suite {
+        println!(
+            "{}: {}",
+            util::format_pattern(bundle, "cipher-suite-label", &mut errors),
+            suite.name()
+        );
+        println!(
+            "{}",
+            util::format_pattern(bundle, "init-config-staged", &mut errors)
+        );
+    }
 
     println!(
         "{}",
@@ -179,60 +246,55 @@ fn init(bundle: &FluentBundle<FluentResource>) {
     );
 }
 
+// set-key 和 rotate-key 中带值的选项
+const KEY_VALUE_FLAGS: [&str; 4] = ["--name", "--m-cost", "--t-cost", "--p-cost"];
+
 fn add_key(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
-    if parameters.is_empty() {
+    let raw = util::has_flag(parameters, "--raw");
+    let key_name = key_name(parameters, bundle);
+    let positional = util::positional_args(parameters, &KEY_VALUE_FLAGS);
+    if positional.is_empty() {
         let mut errors = vec![];
         let value = util::format_pattern(bundle, "set-key-empty-error", &mut errors);
         util::log_error(&value);
         return;
     }
 
-    let key = &parameters[0];
-    if key.len() < 8 {
-        let mut errors = vec![];
-        let value = util::format_pattern(bundle, "set-key-length-error", &mut errors);
-        util::log_error(&value);
-        return;
-    }
-
-    // 获取 Git 仓库根目录
-    let git_root = match util::find_git_root() {
-        Some(path) => path,
-        None => {
-            let mut errors = vec![];
-            util::log_error(&util::format_pattern(
-                bundle,
-                "not-git-repo-error",
-                &mut errors,
-            ));
-            return;
-        }
+    let (git_root, key_file) = match prepare_new_key_file(key_name, bundle) {
+        Some(paths) => paths,
+        None => return,
     };
 
-    // 确保 .git/cryptx 目录存在
-    let encrypt_dir = match util::ensure_git_cryptx_dir(&git_root) {
-        Ok(dir) => dir,
-        Err(e) => {
+    let key = match read_key_argument(positional[0], bundle) {
+        Some(key) => key,
+        None => return,
+    };
+    // 旧仓库的文件使用口令的 SHA-256 加密，口令能解密 HEAD 时沿用旧的派生方式
+    let legacy = (!raw && key.len() >= 8)
+        .then(|| KeyFile::legacy(key.as_bytes()))
+        .filter(|legacy| {
+            Encryptor::from_key(&legacy.key, legacy.kdf)
+                .map_err(|e| e.to_string())
+                .and_then(|e| verify_key_against_head(&git_root, &util::filter_name(key_name), &e))
+                == Ok(true)
+        });
+    let content = match legacy {
+        Some(legacy) => {
             let mut errors = vec![];
-            util::log_error(
-                &util::format_pattern(bundle, "ensure-git-cryptx-dir-error", &mut errors)
-                    .replace("{}", &e.to_string()),
+            println!(
+                "{}",
+                util::format_pattern(bundle, "set-key-legacy", &mut errors)
             );
-            return;
+            legacy
         }
+        None => match new_key_file(&git_root, &key, raw, parameters, bundle) {
+            Some(content) => content,
+            None => return,
+        },
     };
 
-    // 检查密钥文件是否已存在
-    let key_file = encrypt_dir.join("keys").join("global_ase_key");
-    if key_file.exists() {
-        let mut errors = vec![];
-        let value = util::format_pattern(bundle, "set-key-exists-error", &mut errors);
-        util::log_error(&value);
-        return;
-    }
-
     // 将密钥写入文件
-    if let Err(e) = fs::write(&key_file, key) {
+    if let Err(e) = util::write_key_file(&key_file, content.to_toml().as_bytes()) {
         let mut errors = vec![];
         let value = util::format_pattern(bundle, "set-key-write-error", &mut errors);
         util::log_error(&format!("{}: {}", value, e));
@@ -242,45 +304,58 @@ fn add_key(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
     let mut errors = vec![];
     let value = util::format_pattern(bundle, "set-key-success", &mut errors);
     println!("{}", value);
+    register_named_filter(&git_root, key_name, bundle);
 }
 
-fn remove_key(_parameters: &[String], bundle: &FluentBundle<FluentResource>) {
-    // 获取 Git 仓库根目录
-    let git_root = match util::find_git_root() {
-        Some(path) => path,
-        None => {
+fn keygen(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
+    let key_name = key_name(parameters, bundle);
+    let (git_root, key_file) = match prepare_new_key_file(key_name, bundle) {
+        Some(paths) => paths,
+        None => return,
+    };
+
+    // 生成 256 位随机密钥，不需要密钥派生
+    let content = KeyFile::generate();
+    let encryptor = match Encryptor::from_key(&content.key, content.kdf) {
+        Ok(e) => e,
+        Err(e) => {
             let mut errors = vec![];
-            util::log_error(&util::format_pattern(
-                bundle,
-                "not-git-repo-error",
-                &mut errors,
-            ));
+            let value = util::format_pattern(bundle, "keygen-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
             return;
         }
     };
 
-    // 使用新的密钥路径
-    let key_file = util::get_key_path(&git_root);
-    if !key_file.exists() {
-        let mut errors = vec![];
-        let value = util::format_pattern(bundle, "rm-key-not-exists-error", &mut errors);
-        util::log_error(&value);
-        return;
-    }
-
-    if let Err(e) = fs::remove_file(&key_file) {
+    if let Err(e) = util::write_key_file(&key_file, content.to_toml().as_bytes()) {
         let mut errors = vec![];
-        let value = util::format_pattern(bundle, "rm-key-remove-error", &mut errors);
+        let value = util::format_pattern(bundle, "set-key-write-error", &mut errors);
         util::log_error(&format!("{}: {}", value, e));
         return;
     }
 
     let mut errors = vec![];
-    let value = util::format_pattern(bundle, "rm-key-success", &mut errors);
-    println!("{}", value);
+    println!(
+        "{}",
+        util::format_pattern(bundle, "keygen-success", &mut errors)
+    );
+    println!(
+        "{}: {}",
+        util::format_pattern(bundle, "key-fingerprint-label", &mut errors),
+        encryptor.fingerprint_hex()
+    );
+    register_named_filter(&git_root, key_name, bundle);
 }
 
-fn status(bundle: &FluentBundle<FluentResource>) {
+fn export_key(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
+    let key_name = key_name(parameters, bundle);
+    let positional = util::positional_args(parameters, &["--name"]);
+    if positional.is_empty() {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "export-key-empty-error", &mut errors);
+        util::log_error(&value);
+        return;
+    }
+
     let git_root = match util::find_git_root() {
         Some(path) => path,
         None => {
@@ -294,195 +369,245 @@ fn status(bundle: &FluentBundle<FluentResource>) {
         }
     };
 
-    let mut issues = Vec::new();
+    let key_path = util::get_named_key_path(&git_root, key_name);
+    let key_file = match read_current_key(&git_root, key_name, bundle) {
+        Ok(Some(key_file)) => key_file,
+        Ok(None) => {
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "key-not-configured", &mut errors);
+            util::log_error(&value);
+            return;
+        }
+        Err(e) => {
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "export-key-read-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
+            return;
+        }
+    };
 
-    // 检查密钥
-    let mut errors = vec![];
-    if !util::get_key_path(&git_root).exists() {
-        issues.push(util::format_pattern(
-            bundle,
-            "key-not-configured",
-            &mut errors,
-        ));
+    let output = Path::new(positional[0]);
+    if output.exists() {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "export-key-exists-error", &mut errors);
+        util::log_error(&value);
+        return;
     }
 
-    // 检查 .gitattributes
-    if !git_root.join(".gitattributes").exists() {
-        issues.push(util::format_pattern(
-            bundle,
-            "gitattributes-not-configured",
-            &mut errors,
-        ));
-    }
+    // 密钥文件写入后不再修改，使用其修改时间作为创建时间
+    let created = fs::metadata(&key_path)
+        .and_then(|m| m.modified())
+        .unwrap_or_else(|_| SystemTime::now());
+    let export = KeyExport {
+        key_file,
+        created: util::format_utc(created),
+    };
 
-    // 检查 Git 过滤器配置
-    if !util::check_git_filter(&git_root) {
-        issues.push(util::format_pattern(
-            bundle,
-            "git-filter-not-configured",
-            &mut errors,
-        ));
+    if let Err(e) = util::write_key_file(output, export.to_toml().as_bytes()) {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "export-key-write-error", &mut errors);
+        util::log_error(&format!("{}: {}", value, e));
+        return;
     }
 
-    if issues.is_empty() {
-        let value = util::format_pattern(bundle, "status-ok", &mut errors);
-        println!("{}", value);
-        println!(
-            "  {}",
-            util::format_pattern(bundle, "key-configured", &mut errors)
-        );
-        println!(
-            "  {}",
-            util::format_pattern(bundle, "git-filter-configured", &mut errors)
-        );
-    } else {
-        let value = util::format_pattern(bundle, "status-issue", &mut errors);
-        println!("{}", value);
-        for issue in issues {
-            println!("  {}", issue);
-        }
-    }
+    let mut errors = vec![];
+    println!(
+        "{}",
+        util::format_pattern(bundle, "export-key-success", &mut errors)
+    );
+    println!(
+        "{}: {}",
+        util::format_pattern(bundle, "key-fingerprint-label", &mut errors),
+        export.key_file.fingerprint_hex()
+    );
 }
 
-fn clean(parameters: &[String], _bundle: &FluentBundle<FluentResource>) {
-    if parameters.is_empty() {
-        util::log_error("clean-error");
+fn unlock(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
+    let key_name = key_name(parameters, bundle);
+    let identity = util::flag_value(parameters, "--identity");
+    let positional = util::positional_args(parameters, &["--identity", "--name", "--timeout"]);
+    if identity.is_none() && positional.is_empty() {
+        // 没有指定密钥文件时，为受口令保护的密钥打开会话
+        if let Some(git_root) = util::find_git_root() {
+            if is_protected_key(&util::get_named_key_path(&git_root, key_name)) {
+                unlock_session(parameters, &git_root, key_name, bundle);
+                return;
+            }
+        }
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "unlock-empty-error", &mut errors);
+        util::log_error(&value);
         return;
     }
 
-    let file_path = Path::new(&parameters[0]);
-    let git_root = match util::find_git_root() {
-        Some(path) => path,
-        None => {
-            util::log_error("not-git-repo-error");
-            return;
-        }
+    let (git_root, key_path) = match prepare_new_key_file(key_name, bundle) {
+        Some(paths) => paths,
+        None => return,
     };
 
-    // 读取文件内容
-    let content = match fs::read(file_path) {
-        Ok(content) => content,
-        Err(e) => {
-            util::log_error(&format!("{}: {}", "clean-read-error", e));
-            return;
-        }
+    // 使用身份解开为自己包装的仓库密钥，或者导入 export-key 导出的密钥文件
+    let key_file = match identity {
+        Some(identity) => read_wrapped_key(&git_root, key_name, identity, bundle),
+        None => read_exported_key(positional[0], bundle),
     };
-
-    // 获取密钥
-    let key = match fs::read(util::get_key_path(&git_root)) {
-        Ok(key) => key,
-        Err(_) => {
-            // 如果没有密钥，直接输出原内容
-            io::stdout().write_all(&content).unwrap();
-            return;
-        }
+    let key_file = match key_file {
+        Some(key_file) => key_file,
+        None => return,
     };
 
-    // 创建加密器
-    let encryptor = match Encryptor::new(&key) {
+    let encryptor = match Encryptor::from_key(&key_file.key, key_file.kdf) {
         Ok(e) => e,
         Err(e) => {
-            util::log_error(&format!("{}: {}", "clean-encryptor-error", e));
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "unlock-format-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
             return;
         }
     };
 
-    // 加密内容并输出到标准输出
-    match encryptor.encrypt(&content) {
-        Ok(encrypted) => {
-            std::io::stdout().write_all(&encrypted).unwrap();
+    // 导入前确认密钥能解密 HEAD 中的加密文件
+    match verify_key_against_head(&git_root, &util::filter_name(key_name), &encryptor) {
+        Ok(true) => {}
+        Ok(false) => {
+            let mut errors = vec![];
+            println!(
+                "{}",
+                util::format_pattern(bundle, "unlock-unverified", &mut errors)
+            );
         }
         Err(e) => {
-            util::log_error(&format!("{}: {}", "clean-encrypt-error", e));
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "unlock-verify-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
+            return;
         }
     }
-}
 
-fn smudge(parameters: &[String], _bundle: &FluentBundle<FluentResource>) {
-    if parameters.is_empty() {
-        if let Err(_) = io::copy(&mut io::stdin(), &mut io::stdout()) {
-            eprintln!("{}", "smudge-error");
-            std::process::exit(1);
-        }
+    if let Err(e) = util::write_key_file(&key_path, key_file.to_toml().as_bytes()) {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "set-key-write-error", &mut errors);
+        util::log_error(&format!("{}: {}", value, e));
         return;
     }
 
-    let mut content = Vec::new();
-    if let Err(_) = std::io::stdin().read_to_end(&mut content) {
-        eprintln!("{}", "smudge-read-error");
-        std::process::exit(1);
-    }
+    let mut errors = vec![];
+    println!(
+        "{}",
+        util::format_pattern(bundle, "unlock-success", &mut errors)
+    );
+    println!(
+        "{}: {}",
+        util::format_pattern(bundle, "key-fingerprint-label", &mut errors),
+        encryptor.fingerprint_hex()
+    );
+    register_named_filter(&git_root, key_name, bundle);
+    checkout_encrypted_files(&git_root, key_name, bundle);
+}
 
-    // 如果内容不是加密的，直接输出
-    if !Encryptor::is_encrypted(&content) {
-        if let Err(_) = io::stdout().write_all(&content) {
-            eprintln!("{}", "smudge-write-error");
-            std::process::exit(1);
-        }
-        return;
-    }
+// 会话默认的有效时间，单位为分钟
+const SESSION_TIMEOUT_MINUTES: u64 = 60;
 
-    let git_root = match util::find_git_root() {
-        Some(path) => path,
-        None => {
-            eprintln!("{}", "smudge-not-git-error");
-            if let Err(_) = io::stdout().write_all(&content) {
-                std::process::exit(1);
+// 输入口令解开受保护的密钥，会话有效期内过滤器和其他命令直接使用解开的密钥
+fn unlock_session(
+    parameters: &[String],
+    git_root: &Path,
+    key_name: Option<&str>,
+    bundle: &FluentBundle<FluentResource>,
+) {
+    let minutes = match util::flag_value(parameters, "--timeout") {
+        None => SESSION_TIMEOUT_MINUTES,
+        Some(value) => match value.parse::<u64>() {
+            Ok(minutes) if minutes > 0 => minutes,
+            _ => {
+                let mut errors = vec![];
+                let value = util::format_pattern(bundle, "unlock-timeout-error", &mut errors);
+                util::log_error(&value);
+                return;
             }
-            return;
-        }
+        },
     };
 
-    // 获取密钥
-    let key = match fs::read(util::get_key_path(&git_root)) {
-        Ok(key) => key,
+    let protected = match read_protected_key(git_root, key_name) {
+        Ok(protected) => protected,
         Err(e) => {
-            eprintln!("{}: {}", "smudge-key-error", e);
-            if let Err(_) = io::stdout().write_all(&content) {
-                std::process::exit(1);
-            }
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "unlock-format-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
             return;
         }
     };
-
-    // 创建加密器
-    let encryptor = match Encryptor::new(&key) {
-        Ok(e) => e,
-        Err(e) => {
-            eprintln!("{}: {}", "smudge-encryptor-error", e);
-            if let Err(_) = io::stdout().write_all(&content) {
-                std::process::exit(1);
-            }
-            return;
-        }
+    let key_file = match unprotect_key(&protected, bundle) {
+        Some(key_file) => key_file,
+        None => return,
     };
 
-    // 解密内容
-    match encryptor.decrypt(&content) {
-        Ok(decrypted) => {
-            if let Err(_) = io::stdout().write_all(&decrypted) {
-                // 如果写入失败，返回原始内容而不是退出
-                if let Err(_) = io::stdout().write_all(&content) {
-                    std::process::exit(1);
+    if let Err(e) = write_session(git_root, key_name, &key_file, minutes) {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "set-key-write-error", &mut errors);
+        util::log_error(&format!("{}: {}", value, e));
+        return;
+    }
+
+    let mut errors = vec![];
+    println!(
+        "{}: {}",
+        util::format_pattern(bundle, "unlock-session-success", &mut errors),
+        minutes
+    );
+    checkout_encrypted_files(git_root, key_name, bundle);
+}
+
+// 解锁后重新检出工作区中仍为暂存区密文的文件（例如 lock 恢复的文件）。
+// 这些文件的状态与暂存区一致，git checkout 不会覆盖，需要先删除再检出
+fn checkout_encrypted_files(
+    git_root: &Path,
+    key_name: Option<&str>,
+    bundle: &FluentBundle<FluentResource>,
+) {
+    let result = util::list_staged_filtered_files(git_root, &util::filter_name(key_name)).and_then(
+        |entries| {
+            let mut paths = Vec::new();
+            for entry in entries.iter().filter(|e| e.mode.starts_with("100")) {
+                let path = git_root.join(&entry.path);
+                let mut head = Vec::new();
+                let is_encrypted = fs::File::open(&path)
+                    .and_then(|f| {
+                        f.take(Encryptor::MAGIC_HEADER.len() as u64)
+                            .read_to_end(&mut head)
+                    })
+                    .map(|_| Encryptor::is_encrypted(&head))
+                    .unwrap_or(false);
+                if !is_encrypted
+                    || fs::read(&path).ok() != Some(util::read_blob(git_root, &entry.object)?)
+                {
+                    continue;
                 }
+                fs::remove_file(&path).map_err(|e| format!("{}: {}", entry.path, e))?;
+                paths.push(entry.path.clone());
             }
-        }
-        Err(_) => {
-            // 解密失败时，静默返回原始内容
-            if let Err(_) = io::stdout().write_all(&content) {
-                std::process::exit(1);
-            }
+            util::checkout_index_entries(git_root, &paths).map(|_| paths.len())
+        },
+    );
+
+    let mut errors = vec![];
+    match result {
+        Ok(0) => {}
+        Ok(count) => println!(
+            "{}: {}",
+            util::format_pattern(bundle, "unlock-checkout-success", &mut errors),
+            count
+        ),
+        Err(e) => {
+            let value = util::format_pattern(bundle, "unlock-checkout-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
         }
     }
 }
 
-fn diff(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
-    if parameters.is_empty() {
-        util::log_error("diff-error");
-        return;
-    }
-
-    let file_path = Path::new(&parameters[0]);
+// 锁定密钥：删除 .git/cryptx/keys 中的密钥（受口令保护的密钥只清除会话），
+// 并把工作区中使用过滤器的文件恢复为暂存区中的密文，这些文件有未提交的修改时拒绝执行
+fn lock(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
+    let key_name = key_name(parameters, bundle);
     let git_root = match util::find_git_root() {
         Some(path) => path,
         None => {
@@ -496,51 +621,2083 @@ fn diff(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
         }
     };
 
-    // 读取文件内容
-    let content = match fs::read(file_path) {
-        Ok(content) => content,
-        Err(e) => {
-            util::log_error(&format!("{}: {}", "diff-read-error", e));
+    // 同时清理其他密钥已过期的会话
+    remove_expired_sessions(&git_root);
+    let mut errors = vec![];
+    let key_path = util::get_named_key_path(&git_root, key_name);
+    let session_path = util::get_session_path(&git_root, key_name);
+    let remove_key = key_path.exists() && !is_protected_key(&key_path);
+    let forget_session = session_path.exists();
+
+    // 在删除密钥前检查修改，此时过滤器还能比较明文
+    let lock_error = |e: &dyn std::fmt::Display| {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "lock-error", &mut errors);
+        util::log_error(&format!("{}: {}", value, e));
+    };
+    let entries = match util::list_staged_filtered_files(&git_root, &util::filter_name(key_name)) {
+        Ok(entries) => entries,
+        Err(e) => return lock_error(&e),
+    };
+    let paths: Vec<String> = entries.iter().map(|e| e.path.clone()).collect();
+    match util::list_modified_files(&git_root, &paths) {
+        Ok(modified) if !modified.is_empty() => {
+            let value = util::format_pattern(bundle, "lock-modified-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, modified.join(", ")));
             return;
         }
-    };
-
-    // 如果内容不是加密的，直接输出
-    if !Encryptor::is_encrypted(&content) {
-        io::stdout().write_all(&content).unwrap();
-        return;
+        Ok(_) => {}
+        Err(e) => return lock_error(&e),
     }
 
-    // 获取密钥
-    let key = match fs::read(util::get_key_path(&git_root)) {
-        Ok(key) => key,
-        Err(_) => {
-            // 如果没有密钥，输出提示信息
-            println!("{}", "diff-key-not-exists");
-            return;
+    // 先把工作区中的文件恢复为暂存区中的密文，再删除密钥：
+    // 中途失败时密钥仍在，可以重新检出，不会出现留下明文却没有密钥的状态。
+    // 每个文件通过临时文件和重命名写入，之后刷新暂存区的文件状态，文件不会显示为已修改
+    let mut restored = Vec::new();
+    for entry in entries.iter().filter(|e| e.mode.starts_with("100")) {
+        let path = git_root.join(&entry.path);
+        if !path.is_file() {
+            continue;
         }
-    };
-
-    // 创建加密器
-    let encryptor = match Encryptor::new(&key) {
-        Ok(e) => e,
-        Err(e) => {
-            util::log_error(&format!("{}: {}", "diff-encryptor-error", e));
-            return;
+        let result = util::read_blob(&git_root, &entry.object).and_then(|content| {
+            if fs::read(&path).map_err(|e| e.to_string())? == content {
+                return Ok(false);
+            }
+            util::replace_file(&path, &content)
+                .map(|_| true)
+                .map_err(|e| e.to_string())
+        });
+        match result {
+            Ok(true) => restored.push(entry.path.clone()),
+            Ok(false) => {}
+            Err(e) => return lock_error(&format!("{}: {}", entry.path, e)),
+        }
+    }
+    if let Err(e) = util::refresh_index_entries(&git_root, &restored) {
+        return lock_error(&e);
+    }
+
+    // 轮换后保留的旧密钥同样是明文，与当前密钥一起删除；受口令保护的旧密钥保留
+    let retired: Vec<PathBuf> = util::list_retired_key_paths(&git_root, key_name)
+        .into_iter()
+        .filter(|path| !is_protected_key(path))
+        .collect();
+    for path in retired.iter().chain(Some(&key_path).filter(|_| remove_key)) {
+        if let Err(e) = fs::remove_file(path) {
+            return lock_error(&e);
+        }
+    }
+    if forget_session {
+        if let Err(e) = remove_session(&git_root, key_name) {
+            return lock_error(&e);
+        }
+    }
+
+    if !remove_key && !forget_session && retired.is_empty() && restored.is_empty() {
+        println!(
+            "{}",
+            util::format_pattern(bundle, "lock-no-session", &mut errors)
+        );
+        return;
+    }
+    println!(
+        "{}: {}",
+        util::format_pattern(bundle, "lock-success", &mut errors),
+        restored.len()
+    );
+}
+
+// 使用口令保护保存的密钥，--remove 恢复为明文密钥文件
+fn protect_key(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
+    let key_name = key_name(parameters, bundle);
+    let git_root = match util::find_git_root() {
+        Some(path) => path,
+        None => {
+            let mut errors = vec![];
+            util::log_error(&util::format_pattern(
+                bundle,
+                "not-git-repo-error",
+                &mut errors,
+            ));
+            return;
+        }
+    };
+
+    let key_path = util::get_named_key_path(&git_root, key_name);
+    if !key_path.exists() {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "key-not-configured", &mut errors);
+        util::log_error(&value);
+        return;
+    }
+
+    let remove = util::has_flag(parameters, "--remove");
+    if remove != is_protected_key(&key_path) {
+        let id = if remove {
+            "protect-key-not-protected-error"
+        } else {
+            "protect-key-already-error"
+        };
+        let mut errors = vec![];
+        util::log_error(&util::format_pattern(bundle, id, &mut errors));
+        return;
+    }
+
+    let content = if remove {
+        let key_file = match read_protected_key(&git_root, key_name) {
+            Ok(protected) => unprotect_key(&protected, bundle),
+            Err(e) => {
+                let mut errors = vec![];
+                let value = util::format_pattern(bundle, "protect-key-error", &mut errors);
+                util::log_error(&format!("{}: {}", value, e));
+                return;
+            }
+        };
+        match key_file {
+            Some(key_file) => key_file.to_toml(),
+            None => return,
+        }
+    } else {
+        let key_file = match util::read_key_file(&key_path)
+            .map_err(CryptoError::from)
+            .and_then(|c| KeyFile::load(&c))
+        {
+            Ok(key_file) => key_file,
+            Err(e) => {
+                let mut errors = vec![];
+                let value = util::format_pattern(bundle, "protect-key-error", &mut errors);
+                util::log_error(&format!("{}: {}", value, e));
+                return;
+            }
+        };
+        let passphrase = match new_passphrase(bundle) {
+            Some(passphrase) => passphrase,
+            None => return,
+        };
+        match ProtectedKeyFile::protect(&key_file, passphrase.as_bytes(), KdfParams::generate()) {
+            Ok(protected) => Zeroizing::new(protected.to_toml()),
+            Err(e) => {
+                let mut errors = vec![];
+                let value = util::format_pattern(bundle, "protect-key-error", &mut errors);
+                util::log_error(&format!("{}: {}", value, e));
+                return;
+            }
+        }
+    };
+
+    if let Err(e) = util::write_key_file(&key_path, content.as_bytes()) {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "set-key-write-error", &mut errors);
+        util::log_error(&format!("{}: {}", value, e));
+        return;
+    }
+    // 保护状态改变后，之前打开的会话不再需要
+    let _ = remove_session(&git_root, key_name);
+
+    let mut errors = vec![];
+    let id = if remove {
+        "protect-key-remove-success"
+    } else {
+        "protect-key-success"
+    };
+    println!("{}", util::format_pattern(bundle, id, &mut errors));
+}
+
+// 两次输入新口令，确认一致且长度足够
+fn new_passphrase(bundle: &FluentBundle<FluentResource>) -> Option<Zeroizing<String>> {
+    let passphrase = read_passphrase(bundle, "passphrase-prompt")?;
+    if passphrase.len() < 8 {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "set-key-length-error", &mut errors);
+        util::log_error(&value);
+        return None;
+    }
+    if read_passphrase(bundle, "passphrase-confirm-prompt")? != passphrase {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "passphrase-mismatch-error", &mut errors);
+        util::log_error(&value);
+        return None;
+    }
+    Some(passphrase)
+}
+
+fn read_passphrase(bundle: &FluentBundle<FluentResource>, id: &str) -> Option<Zeroizing<String>> {
+    let mut errors = vec![];
+    let prompt = util::format_pattern(bundle, id, &mut errors);
+    match util::read_passphrase(&prompt) {
+        Ok(passphrase) => Some(passphrase),
+        Err(e) => {
+            let value = util::format_pattern(bundle, "passphrase-read-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
+            None
+        }
+    }
+}
+
+// 输入口令解开受保护的密钥
+fn unprotect_key(
+    protected: &ProtectedKeyFile,
+    bundle: &FluentBundle<FluentResource>,
+) -> Option<KeyFile> {
+    let passphrase = read_passphrase(bundle, "passphrase-prompt")?;
+    match protected.unprotect(passphrase.as_bytes()) {
+        Ok(key_file) => Some(key_file),
+        Err(e) => {
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "passphrase-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
+            None
+        }
+    }
+}
+
+fn read_exported_key(path: &str, bundle: &FluentBundle<FluentResource>) -> Option<KeyFile> {
+    let content = match fs::read_to_string(path).map(Zeroizing::new) {
+        Ok(content) => content,
+        Err(e) => {
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "unlock-read-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
+            return None;
+        }
+    };
+
+    match KeyExport::parse(&content) {
+        Ok(export) => Some(export.key_file),
+        Err(e) => {
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "unlock-format-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
+            None
+        }
+    }
+}
+
+fn read_wrapped_key(
+    git_root: &Path,
+    key_name: Option<&str>,
+    identity_path: &str,
+    bundle: &FluentBundle<FluentResource>,
+) -> Option<KeyFile> {
+    let identity = match fs::read_to_string(identity_path)
+        .map(Zeroizing::new)
+        .map_err(|e| e.to_string())
+        .and_then(|c| Identity::parse(&c).map_err(|e| e.to_string()))
+    {
+        Ok(identity) => identity,
+        Err(e) => {
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "identity-read-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
+            return None;
+        }
+    };
+
+    // 包装后的密钥以接收者公钥指纹命名
+    let fingerprint = recipient::public_key_fingerprint(&identity.public_key());
+    let wrapped_path = util::get_recipients_dir(git_root, key_name).join(&fingerprint);
+    let wrapped = match fs::read_to_string(&wrapped_path) {
+        Ok(content) => content,
+        Err(_) => {
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "recipient-not-found-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, fingerprint));
+            return None;
+        }
+    };
+
+    match WrappedKey::parse(&wrapped).and_then(|w| w.unwrap(&identity)) {
+        Ok(key_file) => Some(key_file),
+        Err(e) => {
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "unlock-format-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
+            None
+        }
+    }
+}
+
+fn gen_identity(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
+    if parameters.is_empty() {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "gen-identity-empty-error", &mut errors);
+        util::log_error(&value);
+        return;
+    }
+
+    let path = Path::new(&parameters[0]);
+    if path.exists() {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "export-key-exists-error", &mut errors);
+        util::log_error(&value);
+        return;
+    }
+
+    let identity = Identity::generate();
+    if let Err(e) = util::write_key_file(path, identity.to_toml().as_bytes()) {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "export-key-write-error", &mut errors);
+        util::log_error(&format!("{}: {}", value, e));
+        return;
+    }
+
+    let mut errors = vec![];
+    println!(
+        "{}",
+        util::format_pattern(bundle, "gen-identity-success", &mut errors)
+    );
+    println!(
+        "{}: {}",
+        util::format_pattern(bundle, "public-key-label", &mut errors),
+        hex::encode(identity.public_key().as_bytes())
+    );
+}
+
+fn add_recipient(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
+    let key_name = key_name(parameters, bundle);
+    let positional = util::positional_args(parameters, &["--name"]);
+    if positional.is_empty() {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "add-recipient-empty-error", &mut errors);
+        util::log_error(&value);
+        return;
+    }
+
+    let public_key = match recipient::parse_public_key(positional[0]) {
+        Ok(key) => key,
+        Err(e) => {
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "add-recipient-format-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
+            return;
+        }
+    };
+
+    let git_root = match util::find_git_root() {
+        Some(path) => path,
+        None => {
+            let mut errors = vec![];
+            util::log_error(&util::format_pattern(
+                bundle,
+                "not-git-repo-error",
+                &mut errors,
+            ));
+            return;
+        }
+    };
+
+    let key_file = match require_current_key(&git_root, key_name, bundle) {
+        Some(key_file) => key_file,
+        None => return,
+    };
+
+    let recipients_dir = match util::ensure_recipients_dir(&git_root, key_name) {
+        Ok(dir) => dir,
+        Err(e) => {
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "ensure-git-cryptx-dir-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
+            return;
+        }
+    };
+
+    let fingerprint = recipient::public_key_fingerprint(&public_key);
+    let wrapped_path = recipients_dir.join(&fingerprint);
+    if wrapped_path.exists() {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "add-recipient-exists-error", &mut errors);
+        util::log_error(&value);
+        return;
+    }
+
+    let wrapped = match WrappedKey::wrap(&key_file, &public_key) {
+        Ok(wrapped) => wrapped,
+        Err(e) => {
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "add-recipient-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
+            return;
+        }
+    };
+
+    // 写入并暂存，提交后其他成员即可获取
+    if let Err(e) = fs::write(&wrapped_path, wrapped.to_toml())
+        .map_err(|e| e.to_string())
+        .and_then(|_| util::stage_file(&git_root, &wrapped_path))
+    {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "add-recipient-error", &mut errors);
+        util::log_error(&format!("{}: {}", value, e));
+        return;
+    }
+
+    let mut errors = vec![];
+    println!(
+        "{}: {}",
+        util::format_pattern(bundle, "add-recipient-success", &mut errors),
+        fingerprint
+    );
+}
+
+// 使用当前密钥文件创建密钥环，并加入轮换后保留的旧密钥
+fn load_keyring(
+    git_root: &Path,
+    key_name: Option<&str>,
+    key_file: &KeyFile,
+) -> Result<Keyring, CryptoError> {
+    let mut keyring = Keyring::new(Encryptor::from_key(&key_file.key, key_file.kdf)?);
+    for path in util::list_retired_key_paths(git_root, key_name) {
+        // 无法读取的旧密钥直接跳过
+        if let Ok(encryptor) = util::read_key_file(&path)
+            .map_err(CryptoError::from)
+            .and_then(|c| Encryptor::from_key_file(&c))
+        {
+            keyring.add_retired(encryptor);
+        }
+    }
+    Ok(keyring)
+}
+
+fn is_protected_key(key_path: &Path) -> bool {
+    util::read_key_file(key_path)
+        .map(|c| ProtectedKeyFile::is_protected(&c))
+        .unwrap_or(false)
+}
+
+fn read_protected_key(
+    git_root: &Path,
+    key_name: Option<&str>,
+) -> Result<ProtectedKeyFile, CryptoError> {
+    let content = util::read_key_file(&util::get_named_key_path(git_root, key_name))?;
+    let content =
+        std::str::from_utf8(&content).map_err(|e| CryptoError::KeyError(e.to_string()))?;
+    ProtectedKeyFile::parse(content)
+}
+
+// 读取当前密钥，没有配置密钥时为 None；
+// 受口令保护的密钥从 unlock 打开的会话中读取，没有有效会话时返回错误
+fn read_current_key(
+    git_root: &Path,
+    key_name: Option<&str>,
+    bundle: &FluentBundle<FluentResource>,
+) -> Result<Option<KeyFile>, String> {
+    remove_expired_sessions(git_root);
+    let content = match util::read_key_file(&util::get_named_key_path(git_root, key_name)) {
+        Ok(content) => content,
+        Err(_) => return Ok(None),
+    };
+    if !ProtectedKeyFile::is_protected(&content) {
+        return KeyFile::load(&content).map(Some).map_err(|e| e.to_string());
+    }
+
+    let protected = read_protected_key(git_root, key_name).map_err(|e| e.to_string())?;
+    match read_session(git_root, key_name) {
+        Some(key_file) if key_file.fingerprint_hex() == protected.fingerprint => Ok(Some(key_file)),
+        _ => {
+            let mut errors = vec![];
+            Err(util::format_pattern(bundle, "key-locked-error", &mut errors).to_string())
+        }
+    }
+}
+
+// 过滤器（clean、smudge、diff、filter-process）按以下顺序查找默认密钥：
+// 1. 环境变量 GIT_CRYPTX_KEY 中的密钥
+// 2. 环境变量 GIT_CRYPTX_KEY_FILE 指向的文件中的密钥
+// 3. .git/cryptx/keys 中的密钥文件，受口令保护时使用 unlock 打开的会话
+//
+// 环境变量中可以是密钥文件、export-key 导出的文件或 64 位十六进制密钥，值为空时忽略；
+// 命名密钥不读取环境变量
+const KEY_ENV: &str = "GIT_CRYPTX_KEY";
+const KEY_FILE_ENV: &str = "GIT_CRYPTX_KEY_FILE";
+
+// 读取环境变量提供的密钥，没有设置时为 None
+fn read_env_key(
+    key_name: Option<&str>,
+    bundle: &FluentBundle<FluentResource>,
+) -> Option<Result<KeyFile, String>> {
+    if key_name.is_some() {
+        return None;
+    }
+
+    let (name, content) = if let Some(value) = env::var_os(KEY_ENV).filter(|v| !v.is_empty()) {
+        let content = value
+            .into_string()
+            .map(|v| Zeroizing::new(v.into_bytes()))
+            .map_err(|_| "not UTF-8".to_string());
+        (KEY_ENV, content)
+    } else if let Some(path) = env::var_os(KEY_FILE_ENV).filter(|v| !v.is_empty()) {
+        (
+            KEY_FILE_ENV,
+            util::read_key_file(Path::new(&path)).map_err(|e| e.to_string()),
+        )
+    } else {
+        return None;
+    };
+
+    let result = content
+        .and_then(|c| KeyFile::from_supplied(&c).map_err(|e| e.to_string()))
+        .map_err(|e| {
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "key-env-error", &mut errors);
+            format!("{} {}: {}", value, name, e)
+        });
+    Some(result)
+}
+
+// 命令中读取当前密钥，没有密钥或密钥已锁定时输出错误
+fn require_current_key(
+    git_root: &Path,
+    key_name: Option<&str>,
+    bundle: &FluentBundle<FluentResource>,
+) -> Option<KeyFile> {
+    match read_current_key(git_root, key_name, bundle) {
+        Ok(Some(key_file)) => Some(key_file),
+        Ok(None) => {
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "key-not-configured", &mut errors);
+            util::log_error(&value);
+            None
+        }
+        Err(e) => {
+            util::log_error(&e);
+            None
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// 会话文件是密钥文件加上过期时间（Unix 时间戳）
+fn write_session(
+    git_root: &Path,
+    key_name: Option<&str>,
+    key_file: &KeyFile,
+    minutes: u64,
+) -> Result<(), String> {
+    let path = util::get_session_path(git_root, key_name);
+    if let Some(dir) = path.parent() {
+        util::create_private_dir(dir).map_err(|e| e.to_string())?;
+    }
+    let expires = unix_time().saturating_add(minutes.saturating_mul(60));
+    let content = Zeroizing::new(format!("{}expires = {}\n", *key_file.to_toml(), expires));
+    util::write_key_file(&path, content.as_bytes()).map_err(|e| e.to_string())
+}
+
+// 会话文件中记录的过期时间，无法读取时视为已过期
+fn session_expired(content: &[u8]) -> bool {
+    let expires = std::str::from_utf8(content)
+        .ok()
+        .and_then(|content| {
+            content
+                .lines()
+                .find_map(|line| line.strip_prefix("expires = "))
+        })
+        .and_then(|value| value.parse::<u64>().ok());
+    expires.is_none_or(|expires| unix_time() >= expires)
+}
+
+// 读取未过期的会话，过期的会话文件会被删除
+fn read_session(git_root: &Path, key_name: Option<&str>) -> Option<KeyFile> {
+    let content = util::read_key_file(&util::get_session_path(git_root, key_name)).ok()?;
+    if session_expired(&content) {
+        let _ = remove_session(git_root, key_name);
+        return None;
+    }
+    KeyFile::parse(&content)
+}
+
+// 删除所有已过期的会话。read_session 只删除读到的会话，
+// 不再使用的密钥的会话过期后需要在这里清理，否则解开的密钥会一直留在磁盘上
+fn remove_expired_sessions(git_root: &Path) {
+    let entries = match fs::read_dir(util::get_sessions_dir(git_root)) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if util::read_key_file(&path).is_ok_and(|content| session_expired(&content)) {
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
+
+// 删除会话文件。覆盖写入不能保证擦除磁盘上原来的数据（文件系统可能写到新的块），
+// 因此会话只在有效期内保存，过期后由 read_session 或 remove_expired_sessions 删除
+fn remove_session(git_root: &Path, key_name: Option<&str>) -> io::Result<()> {
+    fs::remove_file(util::get_session_path(git_root, key_name))
+}
+
+// 使用 HEAD 中第一个已加密的文件检验密钥，没有可用于检验的文件时返回 Ok(false)
+fn verify_key_against_head(
+    git_root: &Path,
+    filter: &str,
+    encryptor: &Encryptor,
+) -> Result<bool, String> {
+    for path in util::list_filtered_files(git_root, filter)? {
+        let blob = util::read_head_blob(git_root, &path)?;
+        if !Encryptor::is_encrypted(&blob) {
+            continue;
+        }
+
+        return encryptor
+            .decrypt(&blob)
+            .map(|_| true)
+            .map_err(|e| format!("{}: {}", path, e));
+    }
+
+    Ok(false)
+}
+
+// 解析 --name 指定的密钥名称，未指定时使用默认密钥
+fn key_name<'a>(
+    parameters: &'a [String],
+    bundle: &FluentBundle<FluentResource>,
+) -> Option<&'a str> {
+    checked_key_name(parameters, bundle, util::log_error)
+}
+
+// 过滤器中使用，错误写到标准错误
+fn filter_key_name<'a>(
+    parameters: &'a [String],
+    bundle: &FluentBundle<FluentResource>,
+) -> Option<&'a str> {
+    checked_key_name(parameters, bundle, util::log_filter_error)
+}
+
+fn checked_key_name<'a>(
+    parameters: &'a [String],
+    bundle: &FluentBundle<FluentResource>,
+    log: fn(&str),
+) -> Option<&'a str> {
+    let name = util::flag_value(parameters, "--name")?;
+    if !util::is_valid_key_name(name) {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "key-name-error", &mut errors);
+        log(&format!("{}: {}", value, name));
+    }
+    Some(name)
+}
+
+// 为命名密钥注册 git-cryptx-<名称> 过滤器，并提示如何在 .gitattributes 中使用
+fn register_named_filter(
+    git_root: &Path,
+    key_name: Option<&str>,
+    bundle: &FluentBundle<FluentResource>,
+) {
+    if key_name.is_none() {
+        return;
+    }
+
+    if let Err(e) = util::configure_git_filter(git_root, key_name) {
+        let mut errors = vec![];
+        util::log_error(
+            &util::format_pattern(bundle, "configure-git-filter-error", &mut errors)
+                .replace("{}", &e.to_string()),
+        );
+        return;
+    }
+
+    let filter = util::filter_name(key_name);
+    let mut errors = vec![];
+    println!(
+        "{}: <pattern> filter={} diff={}",
+        util::format_pattern(bundle, "named-filter-hint", &mut errors),
+        filter,
+        filter
+    );
+}
+
+// 查找仓库并确保密钥目录存在，密钥文件已存在时报错，不允许覆盖
+fn prepare_new_key_file(
+    key_name: Option<&str>,
+    bundle: &FluentBundle<FluentResource>,
+) -> Option<(PathBuf, PathBuf)> {
+    // 获取 Git 仓库根目录
+    let git_root = match util::find_git_root() {
+        Some(path) => path,
+        None => {
+            let mut errors = vec![];
+            util::log_error(&util::format_pattern(
+                bundle,
+                "not-git-repo-error",
+                &mut errors,
+            ));
+            return None;
+        }
+    };
+
+    // 确保 .git/cryptx 目录存在
+    if let Err(e) = util::ensure_git_cryptx_dir(&git_root) {
+        let mut errors = vec![];
+        util::log_error(
+            &util::format_pattern(bundle, "ensure-git-cryptx-dir-error", &mut errors)
+                .replace("{}", &e.to_string()),
+        );
+        return None;
+    }
+
+    // 检查密钥文件是否已存在
+    let key_file = util::get_named_key_path(&git_root, key_name);
+    if key_file.exists() {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "set-key-exists-error", &mut errors);
+        util::log_error(&value);
+        return None;
+    }
+
+    Some((git_root, key_file))
+}
+
+// 读取命令行中的密钥参数，"-" 表示从标准输入读取（去掉末尾的换行），
+// 避免密钥出现在进程列表和 shell 历史中
+fn read_key_argument(
+    argument: &str,
+    bundle: &FluentBundle<FluentResource>,
+) -> Option<Zeroizing<String>> {
+    if argument != "-" {
+        return Some(Zeroizing::new(argument.to_string()));
+    }
+
+    let mut input = Zeroizing::new(String::with_capacity(1024));
+    if let Err(e) = io::stdin().read_to_string(&mut input) {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "set-key-stdin-error", &mut errors);
+        util::log_error(&format!("{}: {}", value, e));
+        return None;
+    }
+    let len = input.trim_end_matches(['\r', '\n']).len();
+    input.truncate(len);
+    if input.is_empty() {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "set-key-empty-error", &mut errors);
+        util::log_error(&value);
+        return None;
+    }
+    Some(input)
+}
+
+// 根据用户输入构造新密钥：--raw 模式要求 64 位十六进制（256 位）的高熵密钥，
+// 否则将输入作为口令派生密钥
+fn new_key_file(
+    git_root: &Path,
+    key: &str,
+    raw: bool,
+    parameters: &[String],
+    bundle: &FluentBundle<FluentResource>,
+) -> Option<KeyFile> {
+    if raw {
+        return match SecretKey::from_hex(key) {
+            Some(key) => Some(KeyFile { kdf: Kdf::Raw, key }),
+            None => {
+                let mut errors = vec![];
+                let value = util::format_pattern(bundle, "set-key-raw-format-error", &mut errors);
+                util::log_error(&value);
+                None
+            }
+        };
+    }
+
+    if key.len() < 8 {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "set-key-length-error", &mut errors);
+        util::log_error(&value);
+        return None;
+    }
+
+    derive_passphrase_key(git_root, key, parameters, bundle)
+}
+
+// rotate-key 中带值的选项
+const ROTATE_VALUE_FLAGS: [&str; 5] = [
+    "--name",
+    "--m-cost",
+    "--t-cost",
+    "--p-cost",
+    "--remove-recipient",
+];
+
+fn rotate_key(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
+    let raw = util::has_flag(parameters, "--raw");
+    let key_name = key_name(parameters, bundle);
+    let positional = util::positional_args(parameters, &ROTATE_VALUE_FLAGS);
+    let removed = util::flag_values(parameters, "--remove-recipient");
+
+    let git_root = match util::find_git_root() {
+        Some(path) => path,
+        None => {
+            let mut errors = vec![];
+            util::log_error(&util::format_pattern(
+                bundle,
+                "not-git-repo-error",
+                &mut errors,
+            ));
+            return;
+        }
+    };
+
+    // 轮换后旧密钥文件原样保留，受口令保护的旧密钥无法在过滤器中使用
+    let key_path = util::get_named_key_path(&git_root, key_name);
+    if is_protected_key(&key_path) {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "rotate-key-protected-error", &mut errors);
+        util::log_error(&value);
+        return;
+    }
+    let old_key = match require_current_key(&git_root, key_name, bundle) {
+        Some(key_file) => key_file,
+        None => return,
+    };
+
+    // 未指定新密钥时生成随机密钥
+    let new_key = match positional.first() {
+        Some(key) => match read_key_argument(key, bundle)
+            .and_then(|key| new_key_file(&git_root, &key, raw, parameters, bundle))
+        {
+            Some(key_file) => key_file,
+            None => return,
+        },
+        None => KeyFile::generate(),
+    };
+    if new_key.key == old_key.key {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "rotate-key-same-error", &mut errors);
+        util::log_error(&value);
+        return;
+    }
+
+    match reencrypt_staged_files(&git_root, key_name, &old_key, &new_key, &removed) {
+        Ok((count, recipients)) => {
+            let mut errors = vec![];
+            println!(
+                "{}: {}",
+                util::format_pattern(bundle, "rotate-key-success", &mut errors),
+                count
+            );
+            if recipients > 0 {
+                println!(
+                    "{}: {}",
+                    util::format_pattern(bundle, "rotate-key-recipients", &mut errors),
+                    recipients
+                );
+            }
+            println!(
+                "{}: {}",
+                util::format_pattern(bundle, "key-fingerprint-label", &mut errors),
+                new_key.fingerprint_hex()
+            );
+        }
+        Err(e) => {
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "rotate-key-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
+        }
+    }
+}
+
+// 用新密钥重新加密暂存区中使用该密钥过滤器的文件并替换密钥，旧密钥以只读方式保留，
+// 用于解密历史提交。.git-cryptx/keys 中的接收者改为包装新密钥，removed 中的接收者被删除。
+// 返回重新加密的文件数量和重新包装的接收者数量
+fn reencrypt_staged_files(
+    git_root: &Path,
+    key_name: Option<&str>,
+    old_key: &KeyFile,
+    new_key: &KeyFile,
+    removed: &[&str],
+) -> Result<(usize, usize), String> {
+    // 暂存区中可能还有更早的密钥加密的文件，使用密钥环解密
+    let key_path = util::get_named_key_path(git_root, key_name);
+    let old_keyring = load_keyring(git_root, key_name, old_key).map_err(|e| e.to_string())?;
+    let new_encryptor =
+        Encryptor::from_key(&new_key.key, new_key.kdf).map_err(|e| e.to_string())?;
+    let policy = FilterPolicy::load(git_root);
+    let mut attributes = FilterPolicy::attribute_reader(git_root);
+
+    // 先计算所有新内容，任何文件无法解密时不修改密钥和暂存区
+    let mut updates = Vec::new();
+    for entry in util::list_staged_filtered_files(git_root, &util::filter_name(key_name))? {
+        let blob = util::read_blob(git_root, &entry.object)?;
+        let plaintext = if Encryptor::is_encrypted(&blob) {
+            old_keyring
+                .decrypt(&blob)
+                .map_err(|e| format!("{}: {}", entry.path, e))?
+        } else {
+            blob
+        };
+        let options = policy.encrypt_options(&mut attributes, &entry.path);
+        let encrypted = new_encryptor
+            .encrypt_with(&plaintext, &options)
+            .map_err(|e| format!("{}: {}", entry.path, e))?;
+        updates.push((entry, encrypted));
+    }
+    let rewrapped = rewrap_recipients(git_root, key_name, new_key, removed)?;
+
+    let retired_path = util::get_retired_key_path(git_root, key_name, &old_key.fingerprint_hex());
+    fs::rename(&key_path, &retired_path).map_err(|e| e.to_string())?;
+    let mut permissions = fs::metadata(&retired_path)
+        .map_err(|e| e.to_string())?
+        .permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(&retired_path, permissions).map_err(|e| e.to_string())?;
+    util::write_key_file(&key_path, new_key.to_toml().as_bytes()).map_err(|e| e.to_string())?;
+
+    let count = updates.len();
+    for (mut entry, encrypted) in updates {
+        entry.object = util::write_blob(git_root, encrypted)?;
+        util::update_index_entry(git_root, &entry)?;
+    }
+
+    let recipients = rewrapped.iter().filter(|(_, w)| w.is_some()).count();
+    for (path, wrapped) in rewrapped {
+        match wrapped {
+            Some(wrapped) => fs::write(&path, wrapped.to_toml()),
+            None => fs::remove_file(&path),
+        }
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+        util::stage_file(git_root, &path)?;
+    }
+
+    Ok((count, recipients))
+}
+
+// 为 .git-cryptx/keys 中的每个接收者包装新密钥，removed 中的接收者（公钥指纹）对应 None。
+// 只计算结果，不修改文件
+fn rewrap_recipients(
+    git_root: &Path,
+    key_name: Option<&str>,
+    new_key: &KeyFile,
+    removed: &[&str],
+) -> Result<Vec<(PathBuf, Option<WrappedKey>)>, String> {
+    let recipients_dir = util::get_recipients_dir(git_root, key_name);
+    let mut paths: Vec<PathBuf> = match fs::read_dir(&recipients_dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    paths.sort();
+
+    let fingerprint = |path: &Path| {
+        path.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+    };
+    if let Some(unknown) = removed
+        .iter()
+        .find(|r| !paths.iter().any(|p| fingerprint(p) == **r))
+    {
+        return Err(format!("接收者不存在: {}", unknown));
+    }
+
+    let mut rewrapped = Vec::new();
+    for path in paths {
+        if removed.contains(&fingerprint(&path).as_str()) {
+            rewrapped.push((path, None));
+            continue;
+        }
+        let wrapped = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|c| WrappedKey::parse(&c).map_err(|e| e.to_string()))
+            .and_then(|w| WrappedKey::wrap(new_key, w.recipient()).map_err(|e| e.to_string()))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        rewrapped.push((path, Some(wrapped)));
+    }
+    Ok(rewrapped)
+}
+
+// 绑定路径的文件被 git mv 移动后，暂存区中的密文仍绑定旧路径，在新路径检出会失败；
+// 重新加密这些文件并绑定到当前路径
+fn rebind(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
+    let key_name = key_name(parameters, bundle);
+    let git_root = match util::find_git_root() {
+        Some(path) => path,
+        None => {
+            let mut errors = vec![];
+            util::log_error(&util::format_pattern(
+                bundle,
+                "not-git-repo-error",
+                &mut errors,
+            ));
+            return;
+        }
+    };
+
+    let keyring = match require_current_key(&git_root, key_name, bundle)
+        .map(|key_file| load_keyring(&git_root, key_name, &key_file))
+    {
+        Some(Ok(keyring)) => keyring,
+        Some(Err(e)) => {
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "rebind-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
+            return;
+        }
+        None => return,
+    };
+
+    let mut errors = vec![];
+    match rebind_staged_files(&git_root, key_name, &keyring) {
+        Ok(count) => println!(
+            "{}: {}",
+            util::format_pattern(bundle, "rebind-success", &mut errors),
+            count
+        ),
+        Err(e) => {
+            let value = util::format_pattern(bundle, "rebind-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
+        }
+    }
+}
+
+// 返回重新绑定的文件数量，任何文件无法解密时不修改暂存区
+fn rebind_staged_files(
+    git_root: &Path,
+    key_name: Option<&str>,
+    keyring: &Keyring,
+) -> Result<usize, String> {
+    let policy = FilterPolicy::load(git_root);
+    let mut attributes = FilterPolicy::attribute_reader(git_root);
+    let mut updates = Vec::new();
+    for entry in util::list_staged_filtered_files(git_root, &util::filter_name(key_name))? {
+        let blob = util::read_blob(git_root, &entry.object)?;
+        let bound = Header::parse(&blob)
+            .ok()
+            .and_then(|(header, _)| header.path);
+        if bound.is_none() || bound.as_deref() == Some(entry.path.as_str()) {
+            continue;
+        }
+
+        let plaintext = keyring
+            .decrypt(&blob)
+            .map_err(|e| format!("{}: {}", entry.path, e))?;
+        // 与 clean 使用相同的选项，重新检查工作区时得到相同的密文
+        let options = policy.encrypt_options(&mut attributes, &entry.path);
+        let encrypted = keyring
+            .current()
+            .encrypt_with(&plaintext, &options)
+            .map_err(|e| format!("{}: {}", entry.path, e))?;
+        updates.push((entry, encrypted));
+    }
+
+    let count = updates.len();
+    for (mut entry, encrypted) in updates {
+        entry.object = util::write_blob(git_root, encrypted)?;
+        util::update_index_entry(git_root, &entry)?;
+    }
+
+    Ok(count)
+}
+
+// 使用仓库的 Argon2id 参数从口令派生密钥，首次使用时生成随机盐。
+// 参数写入 .git-cryptx/kdf.toml 并暂存，提交后其他成员用同一口令得到相同的密钥
+fn derive_passphrase_key(
+    git_root: &Path,
+    passphrase: &str,
+    parameters: &[String],
+    bundle: &FluentBundle<FluentResource>,
+) -> Option<KeyFile> {
+    let kdf_path = util::get_kdf_path(git_root);
+    let local_kdf_path = util::get_local_kdf_path(git_root);
+    let existing_path = [&kdf_path, &local_kdf_path]
+        .into_iter()
+        .find(|path| path.exists());
+    let mut params = if let Some(path) = existing_path {
+        match fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|c| KdfParams::parse(&c).map_err(|e| e.to_string()))
+        {
+            Ok(params) => params,
+            Err(e) => {
+                let mut errors = vec![];
+                let value = util::format_pattern(bundle, "set-key-kdf-error", &mut errors);
+                util::log_error(&format!("{}: {}", value, e));
+                return None;
+            }
+        }
+    } else {
+        KdfParams::generate()
+    };
+
+    // 可以通过命令行调整开销
+    for (flag, cost) in [
+        ("--m-cost", &mut params.m_cost),
+        ("--t-cost", &mut params.t_cost),
+        ("--p-cost", &mut params.p_cost),
+    ] {
+        if let Some(value) = util::flag_value(parameters, flag) {
+            match value.parse::<u32>() {
+                Ok(value) => *cost = value,
+                Err(_) => {
+                    let mut errors = vec![];
+                    let value = util::format_pattern(bundle, "set-key-cost-error", &mut errors);
+                    util::log_error(&format!("{}: {}", value, flag));
+                    return None;
+                }
+            }
+        }
+    }
+
+    let key = match params.derive_key(passphrase.as_bytes()) {
+        Ok(key) => key,
+        Err(e) => {
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "set-key-kdf-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
+            return None;
+        }
+    };
+
+    let unchanged = fs::read_to_string(&kdf_path).ok() == Some(params.to_toml());
+    if !unchanged {
+        let result = util::ensure_cryptx_config_dir(git_root)
+            .and_then(|_| fs::write(&kdf_path, params.to_toml()).map_err(|e| e.to_string()))
+            .and_then(|_| util::stage_file(git_root, &kdf_path));
+        if let Err(e) = result {
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "set-key-write-error", &mut errors);
+            util::log_error(&format!("{}: {}", value, e));
+            return None;
+        }
+        let mut errors = vec![];
+        println!(
+            "{}",
+            util::format_pattern(bundle, "set-key-kdf-staged", &mut errors)
+        );
+    }
+
+    Some(KeyFile {
+        kdf: Kdf::Argon2id,
+        key,
+    })
+}
+
+fn remove_key(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
+    let key_name = key_name(parameters, bundle);
+    // 获取 Git 仓库根目录
+    let git_root = match util::find_git_root() {
+        Some(path) => path,
+        None => {
+            let mut errors = vec![];
+            util::log_error(&util::format_pattern(
+                bundle,
+                "not-git-repo-error",
+                &mut errors,
+            ));
+            return;
+        }
+    };
+
+    // 使用新的密钥路径
+    let key_file = util::get_named_key_path(&git_root, key_name);
+    if !key_file.exists() {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "rm-key-not-exists-error", &mut errors);
+        util::log_error(&value);
+        return;
+    }
+
+    if let Err(e) = fs::remove_file(&key_file) {
+        let mut errors = vec![];
+        let value = util::format_pattern(bundle, "rm-key-remove-error", &mut errors);
+        util::log_error(&format!("{}: {}", value, e));
+        return;
+    }
+    let _ = remove_session(&git_root, key_name);
+
+    let mut errors = vec![];
+    let value = util::format_pattern(bundle, "rm-key-success", &mut errors);
+    println!("{}", value);
+}
+
+fn status(bundle: &FluentBundle<FluentResource>) {
+    let git_root = match util::find_git_root() {
+        Some(path) => path,
+        None => {
+            let mut errors = vec![];
+            util::log_error(&util::format_pattern(
+                bundle,
+                "not-git-repo-error",
+                &mut errors,
+            ));
+            return;
+        }
+    };
+
+    remove_expired_sessions(&git_root);
+    let mut issues = Vec::new();
+
+    // 检查密钥
+    let mut errors = vec![];
+    if !util::has_any_key(&git_root) && read_env_key(None, bundle).is_none() {
+        issues.push(util::format_pattern(
+            bundle,
+            "key-not-configured",
+            &mut errors,
+        ));
+    }
+
+    // 检查密钥目录和文件的权限，其他用户不应能读取
+    for (path, mode) in util::list_insecure_key_paths(&git_root) {
+        let value = util::format_pattern(bundle, "key-permissions-warning", &mut errors);
+        issues.push(format!("{}: {} ({:o})", value, path.display(), mode).into());
+    }
+
+    // 检查 .gitattributes
+    if !git_root.join(".gitattributes").exists() {
+        issues.push(util::format_pattern(
+            bundle,
+            "gitattributes-not-configured",
+            &mut errors,
+        ));
+    }
+
+    // 检查 Git 过滤器配置
+    if !util::check_git_filter(&git_root) {
+        issues.push(util::format_pattern(
+            bundle,
+            "git-filter-not-configured",
+            &mut errors,
+        ));
+    }
+
+    if issues.is_empty() {
+        let value = util::format_pattern(bundle, "status-ok", &mut errors);
+        println!("{}", value);
+        println!(
+            "  {}",
+            util::format_pattern(bundle, "key-configured", &mut errors)
+        );
+        println!(
+            "  {}",
+            util::format_pattern(bundle, "git-filter-configured", &mut errors)
+        );
+    } else {
+        let value = util::format_pattern(bundle, "status-issue", &mut errors);
+        println!("{}", value);
+        for issue in issues {
+            println!("  {}", issue);
+        }
+    }
+}
+
+fn clean(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
+    let key_name = filter_key_name(parameters, bundle);
+    // %f 只用于选择密钥，内容必须来自 Git 通过标准输入传入的数据，
+    // 否则 git add -p、git stash、git hash-object --path 等会加密工作区中的内容
+    let positional = util::positional_args(parameters, &["--name"]);
+    if positional.is_empty() {
+        filter_error(bundle, "filter-path-error", &"%f");
+        return;
+    }
+
+    // 逐段加密标准输入并输出到标准输出
+    let keyring = load_filter_keyring(key_name, bundle);
+    let policy = FilterPolicy::load(Path::new("."));
+    let mut attributes = FilterPolicy::attribute_reader(Path::new("."));
+    let options = policy.encrypt_options(&mut attributes, positional[0]);
+    let stdout = io::stdout();
+    let mut writer = io::BufWriter::new(stdout.lock());
+    let result = clean_stream(
+        keyring.as_ref(),
+        policy,
+        &options,
+        io::stdin().lock(),
+        &mut writer,
+    )
+    .and_then(|_| writer.flush().map_err(write_error));
+    if let Err((id, e)) = result {
+        filter_error(bundle, id, &format!("{}: {}", positional[0], e));
+    }
+}
+
+fn smudge(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
+    let key_name = filter_key_name(parameters, bundle);
+    let file_path = util::positional_args(parameters, &["--name"])
+        .first()
+        .copied()
+        .unwrap_or_default();
+
+    // 创建包含旧密钥的密钥环，以便解密历史提交
+    let keyring = load_filter_keyring(key_name, bundle);
+    let stdout = io::stdout();
+    let mut writer = io::BufWriter::new(stdout.lock());
+    let result = smudge_stream(
+        keyring.as_ref(),
+        FilterPolicy::load(Path::new(".")),
+        io::stdin().lock(),
+        &mut writer,
+        file_path,
+        bundle,
+    )
+    .and_then(|_| writer.flush().map_err(write_error));
+    if let Err((id, e)) = result {
+        filter_error(bundle, id, &e);
+    }
+}
+
+// Git 长驻过滤进程（filter.<driver>.process），一次检出或暂存只启动一次
+fn filter_process(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
+    let key_name = filter_key_name(parameters, bundle);
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut reader = io::BufReader::new(stdin.lock());
+    let mut writer = io::BufWriter::new(stdout.lock());
+
+    // 只在进程启动时查找一次仓库并读取密钥
+    let keyring = load_filter_keyring(key_name, bundle);
+    let policy = FilterPolicy::load(Path::new("."));
+    if let Err(e) = run_filter_process(&mut reader, &mut writer, keyring.as_ref(), policy, bundle) {
+        filter_error(bundle, "filter-process-error", &e);
+    }
+}
+
+fn run_filter_process<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    keyring: Option<&Result<Keyring, String>>,
+    policy: FilterPolicy,
+    bundle: &FluentBundle<FluentResource>,
+) -> io::Result<()> {
+    // 握手
+    let welcome = util::read_text_list(reader)?.unwrap_or_default();
+    if welcome.first().map(String::as_str) != Some("git-filter-client")
+        || !welcome.iter().any(|l| l == "version=2")
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "不支持的过滤协议",
+        ));
+    }
+    util::write_text(writer, "git-filter-server")?;
+    util::write_text(writer, "version=2")?;
+    util::write_flush(writer)?;
+    writer.flush()?;
+
+    // 能力协商，只回复双方都支持的能力。接受 delay，但从不延迟文件：解密在本地完成，
+    // 没有需要等待的工作，延迟只会让等待取回的内容堆积在内存或磁盘上。
+    // 因此 smudge 请求中的 can-delay=1 被忽略，list_available_blobs 总是回复空列表
+    let capabilities = util::read_text_list(reader)?.unwrap_or_default();
+    for capability in ["clean", "smudge", "delay"] {
+        let line = format!("capability={}", capability);
+        if capabilities.contains(&line) {
+            util::write_text(writer, &line)?;
+        }
+    }
+    util::write_flush(writer)?;
+    writer.flush()?;
+
+    // 第一次 clean 时启动 git check-attr，之后的文件共用
+    let mut attributes = FilterPolicy::attribute_reader(Path::new("."));
+    while let Some(headers) = util::read_text_list(reader)? {
+        let field = |name: &str| {
+            headers
+                .iter()
+                .find_map(|l| l.strip_prefix(name).and_then(|v| v.strip_prefix('=')))
+                .unwrap_or("")
+        };
+        let command = field("command");
+        let pathname = field("pathname").to_string();
+
+        // 没有延迟的文件，list_available_blobs 请求没有内容部分
+        if command == "list_available_blobs" {
+            util::write_flush(writer)?;
+            util::write_text(writer, "status=success")?;
+            util::write_flush(writer)?;
+            writer.flush()?;
+            continue;
+        }
+
+        // Git 写完一个文件的全部内容后才读取响应，输出必须等输入读完才能开始，
+        // 否则双方都会阻塞在管道上。内容逐包读取并逐段处理，需要暂存的一侧只有密文：
+        // clean 暂存加密结果，smudge 暂存收到的密文，解密后直接输出
+        let mut input = util::PacketReader::new(&mut *reader);
+        let mut spool = Spool::new();
+        let mut response = FilterResponse::new(&mut *writer);
+        let result = match command {
+            "clean" => {
+                let options = policy.encrypt_options(&mut attributes, &pathname);
+                let cleaned = clean_stream(keyring, policy, &options, &mut input, &mut spool)
+                    .map_err(|(id, e)| (id, format!("{}: {}", pathname, e)));
+                input.drain()?;
+                cleaned.and_then(|_| {
+                    let mut spooled = spool.reader().map_err(read_error)?;
+                    io::copy(&mut spooled, &mut response)
+                        .map(|_| ())
+                        .map_err(write_error)
+                })
+            }
+            "smudge" => {
+                let spooled = io::copy(&mut input, &mut spool).map_err(read_error);
+                input.drain()?;
+                spooled.and_then(|_| {
+                    let spooled = spool.reader().map_err(read_error)?;
+                    smudge_stream(keyring, policy, spooled, &mut response, &pathname, bundle)
+                })
+            }
+            _ => {
+                input.drain()?;
+                Err((
+                    "filter-process-error",
+                    format!("unknown command: {}", command),
+                ))
+            }
+        };
+
+        match result {
+            Ok(()) => response.finish()?,
+            Err((id, e)) => {
+                // 单个文件失败时只报告错误，进程继续处理其他文件
+                filter_warning(bundle, id, &e);
+                response.fail()?;
+            }
+        }
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+// 读取过滤器使用的密钥环，没有密钥时为 None
+fn load_filter_keyring(
+    key_name: Option<&str>,
+    bundle: &FluentBundle<FluentResource>,
+) -> Option<Result<Keyring, String>> {
+    match util::find_git_root() {
+        Some(root) => read_env_key(key_name, bundle)
+            .map(|key_file| key_file.map(Some))
+            .unwrap_or_else(|| read_current_key(&root, key_name, bundle))
+            .transpose()
+            .map(|key_file| {
+                key_file.and_then(|key_file| {
+                    load_keyring(&root, key_name, &key_file).map_err(|e| e.to_string())
+                })
+            }),
+        None => {
+            let mut errors = vec![];
+            let value = util::format_pattern(bundle, "not-git-repo-error", &mut errors);
+            Some(Err(value.to_string()))
+        }
+    }
+}
+
+// 没有密钥时 clean 的处理方式，由 cryptx.missingKey 配置，默认拒绝提交明文
+#[derive(Clone, Copy, PartialEq)]
+enum MissingKeyPolicy {
+    Fail,
+    Passthrough,
+}
+
+// 解密失败时 smudge 的处理方式，由 cryptx.smudge 配置，默认警告并保留密文
+#[derive(Clone, Copy, PartialEq)]
+enum SmudgePolicy {
+    Strict,
+    Lenient,
+}
+
+#[derive(Clone, Copy)]
+struct FilterPolicy {
+    missing_key: MissingKeyPolicy,
+    smudge: SmudgePolicy,
+    // 以下设置决定密文的内容，保存在提交到仓库中的 .git-cryptx/config.toml，
+    // 而不是各克隆自己的 git config，否则同一明文在不同克隆中得到不同的密文
+    //
+    // 默认的压缩方式，由 compress 配置，可以用 cryptx-compress 属性按文件覆盖
+    compression: Compression,
+    // 默认的长度填充方式，由 padding 配置，可以用 cryptx-padding 属性按文件覆盖
+    padding: Padding,
+    // 是否把密文绑定到文件路径，由 bind_path 配置，可以用 cryptx-bind-path 属性按文件覆盖
+    bind_path: bool,
+    // 加密套件，由 init --cipher 写入 cipher
+    suite: CipherSuite,
+}
+
+impl FilterPolicy {
+    const MISSING_KEY_CONFIG: &'static str = "cryptx.missingKey";
+    const SMUDGE_CONFIG: &'static str = "cryptx.smudge";
+    const COMPRESS_CONFIG: &'static str = "compress";
+    const COMPRESS_ATTRIBUTE: &'static str = "cryptx-compress";
+    const PADDING_CONFIG: &'static str = "padding";
+    const PADDING_ATTRIBUTE: &'static str = "cryptx-padding";
+    const BIND_PATH_CONFIG: &'static str = "bind_path";
+    const BIND_PATH_ATTRIBUTE: &'static str = "cryptx-bind-path";
+    const CIPHER_CONFIG: &'static str = "cipher";
+    const ATTRIBUTES: [&'static str; 3] = [
+        Self::COMPRESS_ATTRIBUTE,
+        Self::PADDING_ATTRIBUTE,
+        Self::BIND_PATH_ATTRIBUTE,
+    ];
+
+    // Git 在工作区根目录运行过滤器，过滤器中 git_root 为当前目录
+    fn attribute_reader(git_root: &Path) -> util::AttributeReader {
+        util::AttributeReader::new(git_root, &Self::ATTRIBUTES)
+    }
+
+    // missingKey 和 smudge 只影响本地的处理方式，用一次 git config 读取；
+    // 影响密文的设置从 .git-cryptx/config.toml 读取，无效的值使用默认值
+    fn load(git_root: &Path) -> Self {
+        let configs = util::get_git_configs(r"^cryptx\.");
+        let config = |key: &str| configs.get(&key.to_lowercase()).map(String::as_str);
+
+        let missing_key = match config(Self::MISSING_KEY_CONFIG) {
+            Some("passthrough") => MissingKeyPolicy::Passthrough,
+            _ => MissingKeyPolicy::Fail,
+        };
+        let smudge = match config(Self::SMUDGE_CONFIG) {
+            Some("strict") => SmudgePolicy::Strict,
+            _ => SmudgePolicy::Lenient,
+        };
+
+        let settings = Self::read_settings(git_root);
+        let setting = |key: &str| settings.get(key);
+        let compression = setting(Self::COMPRESS_CONFIG)
+            .and_then(|v| v.as_str())
+            .and_then(|name| Compression::from_name(name).ok())
+            .unwrap_or_default();
+        // 块大小可以写成整数或字符串
+        let padding = setting(Self::PADDING_CONFIG)
+            .and_then(|v| match v {
+                toml::Value::Integer(size) => Some(size.to_string()),
+                v => v.as_str().map(str::to_string),
+            })
+            .and_then(|name| Padding::from_name(&name).ok())
+            .unwrap_or_default();
+        let bind_path = setting(Self::BIND_PATH_CONFIG)
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let suite = setting(Self::CIPHER_CONFIG)
+            .and_then(|v| v.as_str())
+            .and_then(|name| CipherSuite::from_name(name).ok())
+            .unwrap_or_default();
+        Self {
+            missing_key,
+            smudge,
+            compression,
+            padding,
+            bind_path,
+            suite,
+        }
+    }
+
+    // 读取 .git-cryptx/config.toml，文件不存在或格式错误时为空表
+    fn read_settings(git_root: &Path) -> toml::value::Table {
+        fs::read_to_string(util::get_config_path(git_root))
+            .ok()
+            .and_then(|content| content.parse::<toml::Value>().ok())
+            .and_then(|value| match value {
+                toml::Value::Table(table) => Some(table),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    // 修改 .git-cryptx/config.toml 中的一项设置并暂存，保留其他设置
+    fn write_setting(git_root: &Path, key: &str, value: toml::Value) -> Result<(), String> {
+        let path = util::get_config_path(git_root);
+        let mut settings = Self::read_settings(git_root);
+        settings.insert(key.to_string(), value);
+        let content = toml::to_string(&toml::Value::Table(settings)).map_err(|e| e.to_string())?;
+        util::ensure_cryptx_config_dir(git_root)?;
+        fs::write(&path, content).map_err(|e| e.to_string())?;
+        util::stage_file(git_root, &path)
+    }
+
+    // 按 .gitattributes 中的属性决定文件的加密选项：
+    // cryptx-compress=zstd|deflate 选择压缩方式，-cryptx-compress 不压缩，
+    // 只写属性名时使用 zstd，未指定或无法识别时使用 compress 设置；
+    // cryptx-padding=padme|<块大小> 选择填充方式，-cryptx-padding 不填充，
+    // 只写属性名时使用 padme，未指定或无法识别时使用 padding 设置；
+    // cryptx-bind-path 绑定路径，-cryptx-bind-path 不绑定，未指定时使用 bind_path 设置
+    //
+    // 属性由 attribute_reader 读取，处理多个文件时共用一个 git check-attr 进程
+    fn encrypt_options<'a>(
+        &self,
+        attributes: &mut util::AttributeReader,
+        pathname: &'a str,
+    ) -> EncryptOptions<'a> {
+        let attributes = attributes.read(pathname);
+        let compression = match attributes[0].as_deref() {
+            None => self.compression,
+            Some("set") => Compression::Zstd,
+            Some("unset") => Compression::None,
+            Some(name) => Compression::from_name(name).unwrap_or(self.compression),
+        };
+        let padding = match attributes[1].as_deref() {
+            None => self.padding,
+            Some("set") => Padding::Padme,
+            Some("unset") => Padding::None,
+            Some(name) => Padding::from_name(name).unwrap_or(self.padding),
+        };
+        let bind_path = match attributes[2].as_deref() {
+            None => self.bind_path,
+            Some(value) => value != "unset",
+        };
+        EncryptOptions {
+            suite: self.suite,
+            compression,
+            padding,
+            path: Some(pathname).filter(|_| bind_path),
+        }
+    }
+}
+
+// 逐段加密，内存中只保存一段数据
+fn clean_stream<R: Read, W: Write>(
+    keyring: Option<&Result<Keyring, String>>,
+    policy: FilterPolicy,
+    options: &EncryptOptions,
+    mut reader: R,
+    writer: &mut W,
+) -> Result<(), (&'static str, String)> {
+    let mut head = Vec::new();
+    (&mut reader)
+        .take(Encryptor::MAGIC_HEADER.len() as u64)
+        .read_to_end(&mut head)
+        .map_err(read_error)?;
+
+    let keyring = match keyring {
+        // 没有可用的密钥时密文（例如 lock 之后的工作区文件）原样保存，不会泄露明文
+        None | Some(Err(_)) if Encryptor::is_encrypted(&head) => {
+            return copy_through(&head, reader, writer);
+        }
+        None if policy.missing_key == MissingKeyPolicy::Passthrough => {
+            return copy_through(&head, reader, writer);
+        }
+        None => {
+            return Err((
+                "filter-missing-key-error",
+                format!("{} = fail", FilterPolicy::MISSING_KEY_CONFIG),
+            ))
+        }
+        Some(Err(e)) => return Err(("filter-key-error", e.clone())),
+        Some(Ok(keyring)) => keyring,
+    };
+    let mut reader = (&head[..]).chain(reader);
+
+    // 已经是密文（例如没有密钥时检出后又提交）时不再重复加密：
+    // 一边用已知密钥校验一边原样输出，无法解密时报错
+    if Encryptor::is_encrypted(&head) {
+        let double_encryption = |e: String| ("filter-double-encryption-error", e);
+        let tee = TeeReader { reader, writer };
+        let mut decrypted = keyring
+            .decrypt_reader(tee)
+            .map_err(|e| double_encryption(e.to_string()))?;
+        io::copy(&mut decrypted, &mut io::sink()).map_err(|e| double_encryption(e.to_string()))?;
+        return Ok(());
+    }
+
+    let encrypt_error = |e: io::Error| ("filter-encrypt-error", e.to_string());
+    let mut encrypt_writer = keyring
+        .current()
+        .encrypt_writer(writer, options)
+        .map_err(encrypt_error)?;
+    io::copy(&mut reader, &mut encrypt_writer).map_err(encrypt_error)?;
+    encrypt_writer.finish().map_err(encrypt_error)?;
+    Ok(())
+}
+
+// 没有密钥时保持加密；解密失败（密钥错误或内容被篡改）时严格模式报错，宽松模式警告并输出原始内容
+//
+// 小文件和旧格式在内存中整体解密；大的分段格式文件逐段解密，
+// 第一段之后才失败时已经输出了部分明文，无论哪种模式都会报错
+fn smudge_stream<R: Read, W: Write>(
+    keyring: Option<&Result<Keyring, String>>,
+    policy: FilterPolicy,
+    mut reader: R,
+    writer: &mut W,
+    pathname: &str,
+    bundle: &FluentBundle<FluentResource>,
+) -> Result<(), (&'static str, String)> {
+    let mut head = Vec::new();
+    (&mut reader)
+        .take(Encryptor::STREAM_HEAD_LEN as u64)
+        .read_to_end(&mut head)
+        .map_err(read_error)?;
+
+    // 如果内容不是加密的，直接输出
+    if !Encryptor::is_encrypted(&head) {
+        return copy_through(&head, reader, writer);
+    }
+
+    let keyring = match keyring {
+        Some(Ok(keyring)) => keyring,
+        Some(Err(e)) => {
+            filter_warning(
+                bundle,
+                "filter-key-warning",
+                &format!("{}: {}", pathname, e),
+            );
+            return copy_through(&head, reader, writer);
+        }
+        None => {
+            filter_warning(bundle, "filter-key-warning", &pathname);
+            return copy_through(&head, reader, writer);
         }
     };
 
-    // 解密内容并输出到标准输出
-    match encryptor.decrypt(&content) {
-        Ok(decrypted) => {
-            std::io::stdout().write_all(&decrypted).unwrap();
+    let header = Header::parse(&head)
+        .ok()
+        .map(|(header, _)| header)
+        .filter(|header| header.version == Header::V3);
+
+    // 绑定了路径的文件只能在该路径检出，被移动或与其他文件交换时不解密
+    if let Some(bound) = header.as_ref().and_then(|header| header.path.as_deref()) {
+        if !pathname.is_empty() && bound != pathname {
+            let mut errors = vec![];
+            let detail = format!(
+                "{} ({}: {})",
+                pathname,
+                util::format_pattern(bundle, "bound-path-label", &mut errors),
+                bound
+            );
+            return match policy.smudge {
+                SmudgePolicy::Strict => Err(("filter-path-mismatch-error", detail)),
+                SmudgePolicy::Lenient => {
+                    filter_warning(bundle, "filter-path-mismatch-warning", &detail);
+                    copy_through(&head, reader, writer)
+                }
+            };
+        }
+    }
+
+    // 绑定的路径使头部变长，补读相应长度，使 head 仍然恰好包含头部和第一段
+    let extra = header
+        .as_ref()
+        .map_or(0, |header| header.to_bytes().len() - Header::PREFIX_LEN);
+    (&mut reader)
+        .take(extra as u64)
+        .read_to_end(&mut head)
+        .map_err(read_error)?;
+    if header.is_none() || head.len() < Encryptor::STREAM_HEAD_LEN + extra {
+        reader.read_to_end(&mut head).map_err(read_error)?;
+        return match keyring.decrypt(&head) {
+            Ok(decrypted) => writer.write_all(&decrypted).map_err(write_error),
+            Err(e) => {
+                smudge_failure(keyring, policy, &head, pathname, &e, bundle)?;
+                writer.write_all(&head).map_err(write_error)
+            }
+        };
+    }
+
+    let encryptor = match header
+        .and_then(|header| header.fingerprint)
+        .and_then(|fp| keyring.find(&fp))
+    {
+        Some(encryptor) => encryptor,
+        None => {
+            let e = CryptoError::KeyError("没有对应指纹的密钥".to_string());
+            smudge_failure(keyring, policy, &head, pathname, &e, bundle)?;
+            return copy_through(&head, reader, writer);
         }
+    };
+
+    // 认证第一段之前还没有输出任何内容，失败时仍可原样输出密文；
+    // head 恰好包含头部和第一段，认证第一段不会读取 reader 中的数据
+    let mut decrypted = match encryptor.decrypt_reader((&head[..]).chain(&mut reader)) {
+        Ok(decrypted) => decrypted,
         Err(e) => {
-            util::log_error(&format!("{}: {}", "diff-decrypt-error", e));
+            smudge_failure(keyring, policy, &head, pathname, &e, bundle)?;
+            return copy_through(&head, reader, writer);
+        }
+    };
+    if let Err(e) = decrypted.verify_first_segment() {
+        drop(decrypted);
+        smudge_failure(keyring, policy, &head, pathname, &e, bundle)?;
+        return copy_through(&head, reader, writer);
+    }
+    io::copy(&mut decrypted, writer).map_err(|e| {
+        (
+            "filter-decrypt-error",
+            decrypt_failure_detail(keyring, &head, pathname, &e, bundle),
+        )
+    })?;
+    Ok(())
+}
+
+// 解密失败时严格模式返回错误，宽松模式只输出警告
+fn smudge_failure(
+    keyring: &Keyring,
+    policy: FilterPolicy,
+    content: &[u8],
+    pathname: &str,
+    error: &dyn std::fmt::Display,
+    bundle: &FluentBundle<FluentResource>,
+) -> Result<(), (&'static str, String)> {
+    let detail = decrypt_failure_detail(keyring, content, pathname, error, bundle);
+    match policy.smudge {
+        SmudgePolicy::Strict => Err(("filter-decrypt-error", detail)),
+        SmudgePolicy::Lenient => {
+            filter_warning(bundle, "filter-decrypt-warning", &detail);
+            Ok(())
+        }
+    }
+}
+
+// 优先报告文件头中记录的密钥指纹，旧格式没有指纹时报告当前密钥
+fn decrypt_failure_detail(
+    keyring: &Keyring,
+    content: &[u8],
+    pathname: &str,
+    error: &dyn std::fmt::Display,
+    bundle: &FluentBundle<FluentResource>,
+) -> String {
+    let fingerprint = Header::parse(content)
+        .ok()
+        .and_then(|(header, _)| header.fingerprint)
+        .map(hex::encode)
+        .unwrap_or_else(|| keyring.current().fingerprint_hex());
+    let mut errors = vec![];
+    format!(
+        "{} ({}: {}): {}",
+        pathname,
+        util::format_pattern(bundle, "key-fingerprint-label", &mut errors),
+        fingerprint,
+        error
+    )
+}
+
+// 先输出已读取的开头，再原样复制剩余内容
+fn copy_through<R: Read, W: Write>(
+    head: &[u8],
+    mut reader: R,
+    writer: &mut W,
+) -> Result<(), (&'static str, String)> {
+    writer.write_all(head).map_err(write_error)?;
+    io::copy(&mut reader, writer).map_err(write_error)?;
+    Ok(())
+}
+
+fn read_error(e: io::Error) -> (&'static str, String) {
+    ("filter-read-error", e.to_string())
+}
+
+fn write_error(e: io::Error) -> (&'static str, String) {
+    ("filter-write-error", e.to_string())
+}
+
+// 读取的同时把数据写到另一处，用于一边校验密文一边原样输出
+struct TeeReader<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: Read, W: Write> Read for TeeReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.reader.read(buf)?;
+        self.writer.write_all(&buf[..len])?;
+        Ok(len)
+    }
+}
+
+// 暂存 filter-process 中一个文件的数据，不超过 MEMORY_LIMIT 时保存在内存中，
+// 超过后转入临时文件，内存占用不随文件大小增长
+struct Spool {
+    memory: Vec<u8>,
+    memory_limit: usize,
+    file: Option<(fs::File, PathBuf)>,
+}
+
+impl Spool {
+    const MEMORY_LIMIT: usize = 8 * 1024 * 1024;
+
+    fn new() -> Self {
+        Self {
+            memory: Vec::new(),
+            memory_limit: Self::MEMORY_LIMIT,
+            file: None,
+        }
+    }
+
+    fn create_file() -> io::Result<(fs::File, PathBuf)> {
+        static COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let count = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("git-cryptx-{}-{}.tmp", std::process::id(), count));
+        let mut options = fs::OpenOptions::new();
+        options.read(true).write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        Ok((options.open(&path)?, path))
+    }
+
+    // 从头读取暂存的数据
+    fn reader(&mut self) -> io::Result<Box<dyn Read + '_>> {
+        match &mut self.file {
+            Some((file, _)) => {
+                io::Seek::seek(file, io::SeekFrom::Start(0))?;
+                Ok(Box::new(io::BufReader::new(file)))
+            }
+            None => Ok(Box::new(&self.memory[..])),
+        }
+    }
+}
+
+impl Write for Spool {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.file.is_none() && self.memory.len() + buf.len() > self.memory_limit {
+            let (mut file, path) = Self::create_file()?;
+            let written = file.write_all(&self.memory);
+            self.file = Some((file, path));
+            written?;
+            self.memory = Vec::new();
+        }
+        match &mut self.file {
+            Some((file, _)) => file.write(buf),
+            None => {
+                self.memory.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        if let Some((file, path)) = self.file.take() {
+            drop(file);
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// filter-process 中一个文件的响应。第一次写入内容时才回复 status=success，
+// 输出任何内容之前失败时只回复 status=error；已经输出部分内容后失败时，
+// 按协议在内容之后再回复 status=error，Git 会丢弃这些内容
+struct FilterResponse<W: Write> {
+    packets: util::PacketWriter<W>,
+    started: bool,
+}
+
+impl<W: Write> FilterResponse<W> {
+    fn new(writer: W) -> Self {
+        Self {
+            packets: util::PacketWriter::new(writer),
+            started: false,
+        }
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.started = true;
+            util::write_text(self.packets.get_mut(), "status=success")?;
+            util::write_flush(self.packets.get_mut())?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.start()?;
+        let mut writer = self.packets.finish()?;
+        // 空列表表示状态不变
+        util::write_flush(&mut writer)
+    }
+
+    fn fail(self) -> io::Result<()> {
+        let mut writer = if self.started {
+            self.packets.finish()?
+        } else {
+            self.packets.into_inner()
+        };
+        util::write_text(&mut writer, "status=error")?;
+        util::write_flush(&mut writer)
+    }
+}
+
+impl<W: Write> Write for FilterResponse<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.start()?;
+        self.packets.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.started {
+            self.packets.flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn diff(parameters: &[String], bundle: &FluentBundle<FluentResource>) {
+    let key_name = filter_key_name(parameters, bundle);
+    let positional = util::positional_args(parameters, &["--name"]);
+    if positional.is_empty() {
+        filter_error(bundle, "filter-path-error", &"diff");
+        return;
+    }
+
+    // 读取文件开头，判断是否加密
+    let read_failed = |e: io::Error| {
+        filter_error(
+            bundle,
+            "filter-read-error",
+            &format!("{}: {}", positional[0], e),
+        )
+    };
+    let mut file = match fs::File::open(positional[0]) {
+        Ok(file) => io::BufReader::new(file),
+        Err(e) => return read_failed(e),
+    };
+    let mut head = Vec::new();
+    if let Err(e) = (&mut file)
+        .take(Encryptor::MAGIC_HEADER.len() as u64)
+        .read_to_end(&mut head)
+    {
+        return read_failed(e);
+    }
+    let reader = (&head[..]).chain(file);
+    let stdout = io::stdout();
+    let mut writer = io::BufWriter::new(stdout.lock());
+
+    // 如果内容不是加密的，直接输出
+    let result = if !Encryptor::is_encrypted(&head) {
+        copy_through(&[], reader, &mut writer)
+    } else {
+        // 创建包含旧密钥的密钥环，以便查看历史提交
+        match load_filter_keyring(key_name, bundle) {
+            // 逐段解密并输出到标准输出
+            Some(Ok(keyring)) => keyring
+                .decrypt_reader(reader)
+                .and_then(|mut decrypted| Ok(io::copy(&mut decrypted, &mut writer)?))
+                .map(|_| ())
+                .map_err(|e| ("filter-decrypt-error", format!("{}: {}", positional[0], e))),
+            Some(Err(e)) => Err(("filter-key-error", e)),
+            None => {
+                // 如果没有密钥，输出原始内容并提示
+                filter_warning(bundle, "filter-key-warning", &positional[0]);
+                copy_through(&[], reader, &mut writer)
+            }
         }
+    };
+
+    if let Err((id, e)) = result.and_then(|_| writer.flush().map_err(write_error)) {
+        filter_error(bundle, id, &e);
     }
 }
 
+// 过滤器的错误信息写到标准错误并以非零状态退出，标准输出只包含文件内容
+fn filter_error(bundle: &FluentBundle<FluentResource>, id: &str, detail: &dyn std::fmt::Display) {
+    let mut errors = vec![];
+    let value = util::format_pattern(bundle, id, &mut errors);
+    util::log_filter_error(&format!("{}: {}", value, detail));
+}
+
+fn filter_warning(bundle: &FluentBundle<FluentResource>, id: &str, detail: &dyn std::fmt::Display) {
+    let mut errors = vec![];
+    let value = util::format_pattern(bundle, id, &mut errors);
+    util::log_filter_warning(&format!("{}: {}", value, detail));
+}
+
 fn reset_files(parameters: &[String], _bundle: &FluentBundle<FluentResource>) {
     if parameters.is_empty() {
         util::log_error("reset-error");
@@ -556,8 +2713,472 @@ fn reset_files(parameters: &[String], _bundle: &FluentBundle<FluentResource>) {
             util::log_error(&format!("{}: {}", "reset-file-error", e));
             return;
         }
-        println!("{}", "reset-file-success");
+        println!("reset-file-success");
     } else {
-        println!("{}", "reset-file-modified");
+        println!("reset-file-modified");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter_request(input: &mut Vec<u8>, command: &str, pathname: &str, content: &[u8]) {
+        util::write_text(input, &format!("command={}", command)).unwrap();
+        util::write_text(input, &format!("pathname={}", pathname)).unwrap();
+        util::write_flush(input).unwrap();
+        util::write_content(input, content).unwrap();
+    }
+
+    fn clean_content(
+        keyring: Option<&Result<Keyring, String>>,
+        policy: FilterPolicy,
+        options: &EncryptOptions,
+        content: Vec<u8>,
+    ) -> Result<Vec<u8>, (&'static str, String)> {
+        let mut output = Vec::with_capacity(content.len());
+        clean_stream(keyring, policy, options, &content[..], &mut output)?;
+        Ok(output)
+    }
+
+    fn smudge_content(
+        keyring: Option<&Result<Keyring, String>>,
+        policy: FilterPolicy,
+        content: Vec<u8>,
+        pathname: &str,
+        bundle: &FluentBundle<FluentResource>,
+    ) -> Result<Vec<u8>, (&'static str, String)> {
+        let mut output = Vec::with_capacity(content.len());
+        smudge_stream(keyring, policy, &content[..], &mut output, pathname, bundle)?;
+        Ok(output)
+    }
+
+    fn policy(missing_key: MissingKeyPolicy, smudge: SmudgePolicy) -> FilterPolicy {
+        FilterPolicy {
+            missing_key,
+            smudge,
+            compression: Compression::None,
+            padding: Padding::None,
+            bind_path: false,
+            suite: CipherSuite::Aes256Gcm,
+        }
+    }
+
+    // 依次读取一个响应的状态、内容和结束列表
+    fn filter_response(output: &mut &[u8]) -> (Vec<String>, Vec<u8>) {
+        let status = util::read_text_list(output).unwrap().unwrap();
+        let content = util::read_content(output).unwrap();
+        assert!(util::read_text_list(output).unwrap().unwrap().is_empty());
+        (status, content)
+    }
+
+    #[test]
+    fn test_clean_without_key() {
+        // 默认拒绝以明文保存
+        let strict = policy(MissingKeyPolicy::Fail, SmudgePolicy::Lenient);
+        let result = clean_content(None, strict, &EncryptOptions::default(), b"secret".to_vec());
+        assert_eq!(result.unwrap_err().0, "filter-missing-key-error");
+
+        let passthrough = policy(MissingKeyPolicy::Passthrough, SmudgePolicy::Lenient);
+        let result = clean_content(
+            None,
+            passthrough,
+            &EncryptOptions::default(),
+            b"secret".to_vec(),
+        );
+        assert_eq!(result.unwrap(), b"secret");
+
+        // 已经是密文时即使没有密钥也原样保存
+        let encryptor =
+            Encryptor::from_key(&SecretKey::from_slice(&[7u8; 32]).unwrap(), Kdf::Raw).unwrap();
+        let encrypted = encryptor.encrypt(b"secret").unwrap();
+        let result = clean_content(None, strict, &EncryptOptions::default(), encrypted.clone());
+        assert_eq!(result.unwrap(), encrypted);
+    }
+
+    #[test]
+    fn test_clean_already_encrypted() {
+        let keyring = Ok(Keyring::new(
+            Encryptor::from_key(&SecretKey::from_slice(&[7u8; 32]).unwrap(), Kdf::Raw).unwrap(),
+        ));
+        let policy = policy(MissingKeyPolicy::Fail, SmudgePolicy::Lenient);
+
+        // 已知密钥的密文原样保存
+        let encrypted = keyring.as_ref().unwrap().encrypt(b"secret").unwrap();
+        let result = clean_content(
+            Some(&keyring),
+            policy,
+            &EncryptOptions::default(),
+            encrypted.clone(),
+        );
+        assert_eq!(result.unwrap(), encrypted);
+
+        // 未知密钥的密文拒绝再次加密
+        let other =
+            Encryptor::from_key(&SecretKey::from_slice(&[8u8; 32]).unwrap(), Kdf::Raw).unwrap();
+        let encrypted = other.encrypt(b"secret").unwrap();
+        let result = clean_content(
+            Some(&keyring),
+            policy,
+            &EncryptOptions::default(),
+            encrypted,
+        );
+        assert_eq!(result.unwrap_err().0, "filter-double-encryption-error");
+    }
+
+    #[test]
+    fn test_smudge_authentication_failure() {
+        let bundle = util::load_locale("en");
+        let keyring = Ok(Keyring::new(
+            Encryptor::from_key(&SecretKey::from_slice(&[7u8; 32]).unwrap(), Kdf::Raw).unwrap(),
+        ));
+        let other =
+            Encryptor::from_key(&SecretKey::from_slice(&[8u8; 32]).unwrap(), Kdf::Raw).unwrap();
+        let encrypted = other.encrypt(b"secret").unwrap();
+
+        // 严格模式报告文件名和密钥指纹
+        let strict = policy(MissingKeyPolicy::Fail, SmudgePolicy::Strict);
+        let (id, detail) = smudge_content(
+            Some(&keyring),
+            strict,
+            encrypted.clone(),
+            "a.secret",
+            &bundle,
+        )
+        .unwrap_err();
+        assert_eq!(id, "filter-decrypt-error");
+        assert!(detail.starts_with("a.secret"));
+        assert!(detail.contains(&other.fingerprint_hex()));
+
+        // 宽松模式保留密文
+        let lenient = policy(MissingKeyPolicy::Fail, SmudgePolicy::Lenient);
+        let result = smudge_content(
+            Some(&keyring),
+            lenient,
+            encrypted.clone(),
+            "a.secret",
+            &bundle,
+        );
+        assert_eq!(result.unwrap(), encrypted);
+    }
+
+    #[test]
+    fn test_smudge_path_mismatch() {
+        let bundle = util::load_locale("en");
+        let keyring = Ok(Keyring::new(
+            Encryptor::from_key(&SecretKey::from_slice(&[7u8; 32]).unwrap(), Kdf::Raw).unwrap(),
+        ));
+        let options = EncryptOptions {
+            path: Some("prod.env"),
+            ..Default::default()
+        };
+        let encrypted = keyring
+            .as_ref()
+            .unwrap()
+            .current()
+            .encrypt_with(b"secret", &options)
+            .unwrap();
+
+        let strict = policy(MissingKeyPolicy::Fail, SmudgePolicy::Strict);
+        let result = smudge_content(
+            Some(&keyring),
+            strict,
+            encrypted.clone(),
+            "prod.env",
+            &bundle,
+        );
+        assert_eq!(result.unwrap(), b"secret");
+
+        // 被交换到其他路径
+        let (id, detail) = smudge_content(
+            Some(&keyring),
+            strict,
+            encrypted.clone(),
+            "dev.env",
+            &bundle,
+        )
+        .unwrap_err();
+        assert_eq!(id, "filter-path-mismatch-error");
+        assert!(detail.starts_with("dev.env") && detail.contains("prod.env"));
+
+        let lenient = policy(MissingKeyPolicy::Fail, SmudgePolicy::Lenient);
+        let result = smudge_content(
+            Some(&keyring),
+            lenient,
+            encrypted.clone(),
+            "dev.env",
+            &bundle,
+        );
+        assert_eq!(result.unwrap(), encrypted);
+    }
+
+    #[test]
+    fn test_protected_key_session() {
+        let bundle = util::load_locale("en");
+        let git_root = env::temp_dir().join(format!("git-cryptx-session-{}", std::process::id()));
+        let key_path = util::get_named_key_path(&git_root, None);
+        fs::create_dir_all(key_path.parent().unwrap()).unwrap();
+
+        let mut params = KdfParams::generate();
+        params.m_cost = 64;
+        params.t_cost = 1;
+        let key_file = KeyFile::generate();
+        let protected = ProtectedKeyFile::protect(&key_file, b"correct horse", params).unwrap();
+        fs::write(&key_path, protected.to_toml()).unwrap();
+
+        // 没有会话时密钥是锁定的
+        assert!(is_protected_key(&key_path));
+        assert!(read_current_key(&git_root, None, &bundle).is_err());
+
+        write_session(&git_root, None, &key_file, 5).unwrap();
+        assert_eq!(
+            read_current_key(&git_root, None, &bundle),
+            Ok(Some(key_file.clone()))
+        );
+
+        // 过期的会话被删除
+        let session_path = util::get_session_path(&git_root, None);
+        let expired = format!("{}expires = {}\n", *key_file.to_toml(), unix_time() - 1);
+        fs::write(&session_path, &expired).unwrap();
+        assert!(read_current_key(&git_root, None, &bundle).is_err());
+        assert!(!session_path.exists());
+
+        // 没有被读取的过期会话也会被清理，未过期的会话保留
+        let other_path = util::get_session_path(&git_root, Some("other"));
+        fs::write(&session_path, &expired).unwrap();
+        write_session(&git_root, Some("other"), &key_file, 5).unwrap();
+        remove_expired_sessions(&git_root);
+        assert!(!session_path.exists());
+        assert!(other_path.exists());
+
+        fs::remove_dir_all(&git_root).unwrap();
+    }
+
+    #[test]
+    fn test_policy_settings_from_config() {
+        let git_root = env::temp_dir().join(format!("git-cryptx-settings-{}", std::process::id()));
+        let config_path = util::get_config_path(&git_root);
+        fs::create_dir_all(config_path.parent().unwrap()).unwrap();
+
+        // 没有配置文件时使用默认值
+        let policy = FilterPolicy::load(&git_root);
+        assert_eq!(policy.suite, CipherSuite::default());
+        assert_eq!(policy.padding, Padding::None);
+        assert!(!policy.bind_path);
+
+        fs::write(
+            &config_path,
+            "cipher = \"chacha20-poly1305\"\ncompress = \"zstd\"\npadding = 4096\nbind_path = true\n",
+        )
+        .unwrap();
+        let policy = FilterPolicy::load(&git_root);
+        assert_eq!(policy.suite, CipherSuite::ChaCha20Poly1305);
+        assert_eq!(policy.compression, Compression::Zstd);
+        assert_eq!(policy.padding, Padding::Block(4096));
+        assert!(policy.bind_path);
+
+        // 无法识别的值使用默认值
+        fs::write(&config_path, "cipher = \"des\"\npadding = \"padme\"\n").unwrap();
+        let policy = FilterPolicy::load(&git_root);
+        assert_eq!(policy.suite, CipherSuite::default());
+        assert_eq!(policy.padding, Padding::Padme);
+
+        fs::remove_dir_all(&git_root).unwrap();
+    }
+
+    #[test]
+    fn test_filter_process_uses_piped_content() {
+        let bundle = util::load_locale("en");
+        let keyring = Ok(Keyring::new(
+            Encryptor::from_key(&SecretKey::from_slice(&[7u8; 32]).unwrap(), Kdf::Raw).unwrap(),
+        ));
+
+        // 部分暂存时 Git 传入的内容与工作区文件不同，路径只用于选择密钥
+        let staged = b"first hunk\n";
+        let mut input = Vec::new();
+        util::write_text(&mut input, "git-filter-client").unwrap();
+        util::write_text(&mut input, "version=2").unwrap();
+        util::write_flush(&mut input).unwrap();
+        util::write_text(&mut input, "capability=clean").unwrap();
+        util::write_text(&mut input, "capability=smudge").unwrap();
+        util::write_text(&mut input, "capability=delay").unwrap();
+        util::write_flush(&mut input).unwrap();
+        filter_request(&mut input, "clean", "missing/notes.secret", staged);
+
+        let mut output = Vec::new();
+        run_filter_process(
+            &mut &input[..],
+            &mut output,
+            Some(&keyring),
+            policy(MissingKeyPolicy::Fail, SmudgePolicy::Lenient),
+            &bundle,
+        )
+        .unwrap();
+
+        let mut reader = &output[..];
+        assert_eq!(
+            util::read_text_list(&mut reader).unwrap().unwrap(),
+            vec!["git-filter-server", "version=2"]
+        );
+        assert_eq!(
+            util::read_text_list(&mut reader).unwrap().unwrap(),
+            vec!["capability=clean", "capability=smudge", "capability=delay"]
+        );
+        let (status, encrypted) = filter_response(&mut reader);
+        assert_eq!(status, vec!["status=success"]);
+        assert!(Encryptor::is_encrypted(&encrypted));
+        assert_eq!(
+            keyring.as_ref().unwrap().decrypt(&encrypted).unwrap(),
+            staged
+        );
+
+        // smudge 还原同样的内容
+        let mut input = Vec::new();
+        util::write_text(&mut input, "git-filter-client").unwrap();
+        util::write_text(&mut input, "version=2").unwrap();
+        util::write_flush(&mut input).unwrap();
+        util::write_text(&mut input, "capability=smudge").unwrap();
+        util::write_text(&mut input, "capability=delay").unwrap();
+        util::write_flush(&mut input).unwrap();
+        // 可以延迟时也立即返回内容，之后没有可取回的文件
+        util::write_text(&mut input, "command=smudge").unwrap();
+        util::write_text(&mut input, "pathname=missing/notes.secret").unwrap();
+        util::write_text(&mut input, "can-delay=1").unwrap();
+        util::write_flush(&mut input).unwrap();
+        util::write_content(&mut input, &encrypted).unwrap();
+        util::write_text(&mut input, "command=list_available_blobs").unwrap();
+        util::write_flush(&mut input).unwrap();
+
+        let mut output = Vec::new();
+        run_filter_process(
+            &mut &input[..],
+            &mut output,
+            Some(&keyring),
+            policy(MissingKeyPolicy::Fail, SmudgePolicy::Lenient),
+            &bundle,
+        )
+        .unwrap();
+        let mut reader = &output[..];
+        util::read_text_list(&mut reader).unwrap();
+        util::read_text_list(&mut reader).unwrap();
+        let (status, decrypted) = filter_response(&mut reader);
+        assert_eq!(status, vec!["status=success"]);
+        assert_eq!(decrypted, staged);
+        assert!(util::read_text_list(&mut reader)
+            .unwrap()
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            util::read_text_list(&mut reader).unwrap().unwrap(),
+            vec!["status=success"]
+        );
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_clean_encrypts_piped_content() {
+        let dir = env::temp_dir().join(format!("git-cryptx-partial-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let pathname = dir.join("notes.secret");
+        let pathname = pathname.to_str().unwrap();
+        let keyring = Ok(Keyring::new(
+            Encryptor::from_key(&SecretKey::from_slice(&[7u8; 32]).unwrap(), Kdf::Raw).unwrap(),
+        ));
+
+        // git add -p 只暂存第一处修改：工作区文件包含两处修改，标准输入只有第一处
+        fs::write(pathname, b"first hunk\nsecond hunk\n").unwrap();
+        let staged = b"first hunk\nunchanged\n";
+        let options = EncryptOptions {
+            path: Some(pathname),
+            ..EncryptOptions::default()
+        };
+        let mut encrypted = Vec::new();
+        clean_stream(
+            Some(&keyring),
+            policy(MissingKeyPolicy::Fail, SmudgePolicy::Lenient),
+            &options,
+            &staged[..],
+            &mut encrypted,
+        )
+        .unwrap();
+
+        let decrypted = keyring.as_ref().unwrap().decrypt(&encrypted).unwrap();
+        assert_eq!(decrypted, staged);
+        assert_ne!(decrypted, fs::read(pathname).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_spool_moves_to_file() {
+        let mut spool = Spool::new();
+        spool.memory_limit = 16;
+        spool.write_all(b"0123456789").unwrap();
+        assert!(spool.file.is_none());
+        spool.write_all(b"abcdefghij").unwrap();
+        let path = spool.file.as_ref().unwrap().1.clone();
+        assert!(spool.memory.is_empty());
+
+        let mut content = Vec::new();
+        spool.reader().unwrap().read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"0123456789abcdefghij");
+        drop(spool);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_filter_process_streams_segments() {
+        let bundle = util::load_locale("en");
+        let keyring = Ok(Keyring::new(
+            Encryptor::from_key(&SecretKey::from_slice(&[7u8; 32]).unwrap(), Kdf::Raw).unwrap(),
+        ));
+        let plaintext: Vec<u8> = (0..Encryptor::STREAM_CHUNK_SIZE * 3 + 100)
+            .map(|i| i as u8)
+            .collect();
+        let encrypted = keyring
+            .as_ref()
+            .unwrap()
+            .current()
+            .encrypt_with(&plaintext, &EncryptOptions::default())
+            .unwrap();
+        // 篡改最后一段：第一段认证通过后已经开始输出，之后才发现错误
+        let mut tampered = encrypted.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+
+        let mut input = Vec::new();
+        util::write_text(&mut input, "git-filter-client").unwrap();
+        util::write_text(&mut input, "version=2").unwrap();
+        util::write_flush(&mut input).unwrap();
+        util::write_text(&mut input, "capability=smudge").unwrap();
+        util::write_flush(&mut input).unwrap();
+        filter_request(&mut input, "smudge", "dump.sql", &tampered);
+        filter_request(&mut input, "smudge", "dump.sql", &encrypted);
+
+        let mut output = Vec::new();
+        run_filter_process(
+            &mut &input[..],
+            &mut output,
+            Some(&keyring),
+            policy(MissingKeyPolicy::Fail, SmudgePolicy::Lenient),
+            &bundle,
+        )
+        .unwrap();
+        let mut reader = &output[..];
+        util::read_text_list(&mut reader).unwrap();
+        util::read_text_list(&mut reader).unwrap();
+
+        // 部分内容之后是 status=error
+        let status = util::read_text_list(&mut reader).unwrap().unwrap();
+        assert_eq!(status, vec!["status=success"]);
+        let partial = util::read_content(&mut reader).unwrap();
+        assert!(!partial.is_empty() && partial.len() < plaintext.len());
+        assert_eq!(partial[..], plaintext[..partial.len()]);
+        let status = util::read_text_list(&mut reader).unwrap().unwrap();
+        assert_eq!(status, vec!["status=error"]);
+
+        // 下一个请求不受影响
+        let (status, decrypted) = filter_response(&mut reader);
+        assert_eq!(status, vec!["status=success"]);
+        assert_eq!(decrypted, plaintext);
     }
 }
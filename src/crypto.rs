@@ -1,9 +1,20 @@
 use aes_gcm::{
-    aead::{Aead, KeyInit},
-    Aes256Gcm, Nonce,
+    aead::{generic_array::GenericArray, Aead, KeyInit, Payload},
+    Aes256Gcm,
 };
+use aes_gcm_siv::Aes256GcmSiv;
+use argon2::{Algorithm, Argon2, Version};
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305};
+use flate2::{bufread::DeflateDecoder, write::DeflateEncoder};
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
 use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
 use thiserror::Error;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+type HmacSha256 = Hmac<Sha256>;
 
 #[derive(Error, Debug)]
 pub enum CryptoError {
@@ -15,15 +26,998 @@ pub enum CryptoError {
     KeyError(String),
 }
 
+// 32 字节密钥，离开作用域时清零
+//
+// 不实现 Display，Debug 不输出密钥内容，需要原始字节时显式调用 expose
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct SecretKey([u8; SecretKey::SIZE]);
+
+impl SecretKey {
+    pub const SIZE: usize = 32;
+
+    // 生成随机密钥
+    pub fn generate() -> Self {
+        let mut key = Self([0u8; Self::SIZE]);
+        OsRng.fill_bytes(&mut key.0);
+        key
+    }
+
+    // 长度不是 32 字节时返回 None
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        let mut key = Self([0u8; Self::SIZE]);
+        if bytes.len() != Self::SIZE {
+            return None;
+        }
+        key.0.copy_from_slice(bytes);
+        Some(key)
+    }
+
+    // 解析十六进制密钥，解码的中间结果同样会被清零
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let bytes = Zeroizing::new(hex::decode(hex).ok()?);
+        Self::from_slice(&bytes)
+    }
+
+    pub fn to_hex(&self) -> Zeroizing<String> {
+        Zeroizing::new(hex::encode(self.0))
+    }
+
+    pub fn expose(&self) -> &[u8; Self::SIZE] {
+        &self.0
+    }
+}
+
+// 按常量时间比较，避免通过比较耗时推测密钥
+impl PartialEq for SecretKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+    }
+}
+
+impl Eq for SecretKey {}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretKey(..)")
+    }
+}
+
+// 加密套件标识，v3 格式可以选择，v1/v2 固定为 AES-256-GCM
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CipherSuite {
+    #[default]
+    Aes256Gcm = 1,
+    // 没有 AES 硬件指令的 CPU（例如部分 ARM）上更快
+    ChaCha20Poly1305 = 2,
+    // 使用 24 字节 nonce
+    XChaCha20Poly1305 = 3,
+    // 抗 nonce 误用
+    Aes256GcmSiv = 4,
+}
+
+impl CipherSuite {
+    pub const ALL: [Self; 4] = [
+        Self::Aes256Gcm,
+        Self::ChaCha20Poly1305,
+        Self::XChaCha20Poly1305,
+        Self::Aes256GcmSiv,
+    ];
+
+    fn from_u8(value: u8) -> Result<Self, CryptoError> {
+        Self::ALL
+            .into_iter()
+            .find(|suite| *suite as u8 == value)
+            .ok_or_else(|| CryptoError::EncryptError(format!("不支持的加密套件: {}", value)))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Aes256Gcm => "aes-256-gcm",
+            Self::ChaCha20Poly1305 => "chacha20-poly1305",
+            Self::XChaCha20Poly1305 => "xchacha20-poly1305",
+            Self::Aes256GcmSiv => "aes-256-gcm-siv",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self, CryptoError> {
+        Self::ALL
+            .into_iter()
+            .find(|suite| suite.name() == name)
+            .ok_or_else(|| CryptoError::EncryptError(format!("不支持的加密套件: {}", name)))
+    }
+
+    fn nonce_size(&self) -> usize {
+        match self {
+            Self::XChaCha20Poly1305 => 24,
+            _ => Encryptor::NONCE_SIZE,
+        }
+    }
+
+    // 每段的长度：nonce + 一整段明文的密文 + 认证标签
+    fn segment_size(&self) -> usize {
+        self.nonce_size() + Encryptor::STREAM_CHUNK_SIZE + Encryptor::TAG_SIZE
+    }
+
+    // 每种套件使用独立的子密钥，AES-256-GCM 沿用 v1 的标签以兼容已有的密文
+    fn key_label(&self) -> &'static [u8] {
+        match self {
+            Self::Aes256Gcm => b"git-cryptx/v1/enc",
+            Self::ChaCha20Poly1305 => b"git-cryptx/v3/enc/chacha20-poly1305",
+            Self::XChaCha20Poly1305 => b"git-cryptx/v3/enc/xchacha20-poly1305",
+            Self::Aes256GcmSiv => b"git-cryptx/v3/enc/aes-256-gcm-siv",
+        }
+    }
+}
+
+// 按加密套件分派的 AEAD 算法
+enum SuiteCipher {
+    Aes256Gcm(Aes256Gcm),
+    ChaCha20Poly1305(ChaCha20Poly1305),
+    XChaCha20Poly1305(XChaCha20Poly1305),
+    Aes256GcmSiv(Aes256GcmSiv),
+}
+
+impl SuiteCipher {
+    fn new(suite: CipherSuite, key: &SecretKey) -> Self {
+        let key = GenericArray::from_slice(key.expose());
+        match suite {
+            CipherSuite::Aes256Gcm => Self::Aes256Gcm(Aes256Gcm::new(key)),
+            CipherSuite::ChaCha20Poly1305 => Self::ChaCha20Poly1305(ChaCha20Poly1305::new(key)),
+            CipherSuite::XChaCha20Poly1305 => Self::XChaCha20Poly1305(XChaCha20Poly1305::new(key)),
+            CipherSuite::Aes256GcmSiv => Self::Aes256GcmSiv(Aes256GcmSiv::new(key)),
+        }
+    }
+
+    fn suite(&self) -> CipherSuite {
+        match self {
+            Self::Aes256Gcm(_) => CipherSuite::Aes256Gcm,
+            Self::ChaCha20Poly1305(_) => CipherSuite::ChaCha20Poly1305,
+            Self::XChaCha20Poly1305(_) => CipherSuite::XChaCha20Poly1305,
+            Self::Aes256GcmSiv(_) => CipherSuite::Aes256GcmSiv,
+        }
+    }
+
+    // nonce 的长度必须等于套件的 nonce_size
+    fn encrypt<'m, 'a>(
+        &self,
+        nonce: &[u8],
+        payload: impl Into<Payload<'m, 'a>>,
+    ) -> Result<Vec<u8>, CryptoError> {
+        match self {
+            Self::Aes256Gcm(cipher) => cipher.encrypt(GenericArray::from_slice(nonce), payload),
+            Self::ChaCha20Poly1305(cipher) => {
+                cipher.encrypt(GenericArray::from_slice(nonce), payload)
+            }
+            Self::XChaCha20Poly1305(cipher) => {
+                cipher.encrypt(GenericArray::from_slice(nonce), payload)
+            }
+            Self::Aes256GcmSiv(cipher) => cipher.encrypt(GenericArray::from_slice(nonce), payload),
+        }
+        .map_err(|e| CryptoError::EncryptError(e.to_string()))
+    }
+
+    fn decrypt<'m, 'a>(
+        &self,
+        nonce: &[u8],
+        payload: impl Into<Payload<'m, 'a>>,
+    ) -> Result<Vec<u8>, CryptoError> {
+        match self {
+            Self::Aes256Gcm(cipher) => cipher.decrypt(GenericArray::from_slice(nonce), payload),
+            Self::ChaCha20Poly1305(cipher) => {
+                cipher.decrypt(GenericArray::from_slice(nonce), payload)
+            }
+            Self::XChaCha20Poly1305(cipher) => {
+                cipher.decrypt(GenericArray::from_slice(nonce), payload)
+            }
+            Self::Aes256GcmSiv(cipher) => cipher.decrypt(GenericArray::from_slice(nonce), payload),
+        }
+        .map_err(|e| CryptoError::EncryptError(e.to_string()))
+    }
+}
+
+// 加密前对明文进行的压缩，记录在头部标志位中
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None = 0,
+    Deflate = 1,
+    Zstd = 2,
+}
+
+impl Compression {
+    // 压缩级别固定，相同内容的密文保持不变
+    const DEFLATE_LEVEL: u32 = 6;
+    const ZSTD_LEVEL: i32 = 3;
+
+    fn from_flags(flags: u8) -> Result<Self, CryptoError> {
+        match flags & Header::COMPRESSION_MASK {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Deflate),
+            2 => Ok(Self::Zstd),
+            value => Err(CryptoError::EncryptError(format!(
+                "不支持的压缩方式: {}",
+                value
+            ))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Deflate => "deflate",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self, CryptoError> {
+        match name {
+            "none" => Ok(Self::None),
+            "deflate" => Ok(Self::Deflate),
+            "zstd" => Ok(Self::Zstd),
+            _ => Err(CryptoError::EncryptError(format!(
+                "不支持的压缩方式: {}",
+                name
+            ))),
+        }
+    }
+}
+
+// 压缩之后、加密之前追加的填充，用于隐藏明文长度，记录在头部标志位中
+//
+// 填充为一个 0x80 字节后接若干 0 字节，解密时去掉末尾的 0 和 0x80，
+// 不需要预先知道明文长度，可以流式处理
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Padding {
+    #[default]
+    None,
+    // 填充到指定字节数的整数倍
+    Block(u32),
+    // Padmé：填充后长度只保留 O(log log L) 位有效位，额外开销不超过约 12%
+    Padme,
+}
+
+impl Padding {
+    pub const MARKER: u8 = 0x80;
+
+    fn flags(&self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Block(_) => Header::PADDING_BLOCK,
+            Self::Padme => Header::PADDING_PADME,
+        }
+    }
+
+    // 长度为 len（包含 0x80 标记）的数据填充后的长度
+    fn padded_len(&self, len: u64) -> u64 {
+        match *self {
+            Self::None => len,
+            Self::Block(size) => len.div_ceil(size as u64) * size as u64,
+            Self::Padme => {
+                if len < 2 {
+                    return len;
+                }
+                let exponent = 63 - len.leading_zeros() as u64;
+                let size_bits = 64 - exponent.leading_zeros() as u64;
+                let mask = (1u64 << (exponent - size_bits)) - 1;
+                (len + mask) & !mask
+            }
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Self::None => "none".to_string(),
+            Self::Block(size) => size.to_string(),
+            Self::Padme => "padme".to_string(),
+        }
+    }
+
+    // none、padme 或以字节为单位的块大小
+    pub fn from_name(name: &str) -> Result<Self, CryptoError> {
+        match name {
+            "none" => Ok(Self::None),
+            "padme" => Ok(Self::Padme),
+            _ => match name.parse::<u32>() {
+                Ok(size) if size > 0 => Ok(Self::Block(size)),
+                _ => Err(CryptoError::EncryptError(format!(
+                    "不支持的填充方式: {}",
+                    name
+                ))),
+            },
+        }
+    }
+}
+
+// 加密选项，由过滤器按文件决定
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncryptOptions<'a> {
+    pub suite: CipherSuite,
+    pub compression: Compression,
+    pub padding: Padding,
+    // 绑定的仓库相对路径，记录在头部中并参与认证，移动或交换文件后无法通过路径校验
+    pub path: Option<&'a str>,
+}
+
+impl EncryptOptions<'_> {
+    fn flags(&self) -> u8 {
+        let path_bound = match self.path {
+            Some(_) => Header::PATH_BOUND,
+            None => 0,
+        };
+        self.compression as u8 | self.padding.flags() | path_bound
+    }
+}
+
+// 由用户密钥得到加密密钥所使用的密钥派生方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kdf {
+    // 高熵随机密钥，不做派生
+    Raw = 0,
+    // 旧版本：对密钥字符串做一次 SHA-256
+    Sha256 = 1,
+    // 口令经过 Argon2id 派生
+    Argon2id = 2,
+}
+
+impl Kdf {
+    fn from_u8(value: u8) -> Result<Self, CryptoError> {
+        match value {
+            0 => Ok(Self::Raw),
+            1 => Ok(Self::Sha256),
+            2 => Ok(Self::Argon2id),
+            _ => Err(CryptoError::EncryptError(format!(
+                "不支持的密钥派生方式: {}",
+                value
+            ))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Raw => "raw",
+            Self::Sha256 => "sha256",
+            Self::Argon2id => "argon2id",
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Self, CryptoError> {
+        match name {
+            "raw" => Ok(Self::Raw),
+            "sha256" => Ok(Self::Sha256),
+            "argon2id" => Ok(Self::Argon2id),
+            _ => Err(CryptoError::KeyError(format!(
+                "不支持的密钥派生方式: {}",
+                name
+            ))),
+        }
+    }
+}
+
+// Argon2id 参数，每个仓库一份，保存在提交到仓库中的 .git-cryptx/kdf.toml，
+// 各成员用同一口令派生出相同的密钥
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KdfParams {
+    pub salt: [u8; KdfParams::SALT_SIZE],
+    // 内存开销，单位 KiB
+    pub m_cost: u32,
+    // 迭代次数
+    pub t_cost: u32,
+    // 并行度
+    pub p_cost: u32,
+}
+
+impl KdfParams {
+    pub const SALT_SIZE: usize = 16;
+    pub const DEFAULT_M_COST: u32 = 19 * 1024;
+    pub const DEFAULT_T_COST: u32 = 2;
+    pub const DEFAULT_P_COST: u32 = 1;
+
+    // 生成随机盐和默认开销的参数
+    pub fn generate() -> Self {
+        let mut salt = [0u8; Self::SALT_SIZE];
+        OsRng.fill_bytes(&mut salt);
+        Self {
+            salt,
+            m_cost: Self::DEFAULT_M_COST,
+            t_cost: Self::DEFAULT_T_COST,
+            p_cost: Self::DEFAULT_P_COST,
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Self, CryptoError> {
+        let value: toml::Value = content
+            .parse()
+            .map_err(|e: toml::de::Error| CryptoError::KeyError(e.to_string()))?;
+
+        let algorithm = value.get("algorithm").and_then(|v| v.as_str());
+        if algorithm != Some(Kdf::Argon2id.name()) {
+            return Err(CryptoError::KeyError("不支持的密钥派生算法".to_string()));
+        }
+
+        let salt = value
+            .get("salt")
+            .and_then(|v| v.as_str())
+            .and_then(|v| hex::decode(v).ok())
+            .filter(|v| v.len() == Self::SALT_SIZE)
+            .ok_or_else(|| CryptoError::KeyError("盐格式错误".to_string()))?;
+        let cost = |name: &str| {
+            value
+                .get(name)
+                .and_then(|v| v.as_integer())
+                .and_then(|v| u32::try_from(v).ok())
+                .ok_or_else(|| CryptoError::KeyError(format!("{} 格式错误", name)))
+        };
+
+        let mut params = Self {
+            salt: [0u8; Self::SALT_SIZE],
+            m_cost: cost("m_cost")?,
+            t_cost: cost("t_cost")?,
+            p_cost: cost("p_cost")?,
+        };
+        params.salt.copy_from_slice(&salt);
+        Ok(params)
+    }
+
+    pub fn to_toml(&self) -> String {
+        format!(
+            "algorithm = \"{}\"\nsalt = \"{}\"\nm_cost = {}\nt_cost = {}\np_cost = {}\n",
+            Kdf::Argon2id.name(),
+            hex::encode(self.salt),
+            self.m_cost,
+            self.t_cost,
+            self.p_cost
+        )
+    }
+
+    // 使用 Argon2id 从口令派生 32 字节密钥
+    pub fn derive_key(&self, passphrase: &[u8]) -> Result<SecretKey, CryptoError> {
+        let params = argon2::Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .map_err(|e| CryptoError::KeyError(e.to_string()))?;
+        let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+
+        let mut key = SecretKey([0u8; SecretKey::SIZE]);
+        argon2
+            .hash_password_into(passphrase, &self.salt, &mut key.0)
+            .map_err(|e| CryptoError::KeyError(e.to_string()))?;
+        Ok(key)
+    }
+}
+
+// 保存在 .git/cryptx/keys 中的密钥文件
+//
+// 旧版本直接保存用户输入的密钥字符串，新版本保存派生后的密钥及派生方式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyFile {
+    pub kdf: Kdf,
+    pub key: SecretKey,
+}
+
+// 从解析出的 toml 中取出保存密钥的字段。toml::Value 丢弃时不会清零，
+// 取出后由 Zeroizing 持有，不在内存中留下密钥的副本
+pub(crate) fn take_secret_field(value: &mut toml::Value, name: &str) -> Option<Zeroizing<String>> {
+    match value.as_table_mut()?.remove(name)? {
+        toml::Value::String(secret) => Some(Zeroizing::new(secret)),
+        _ => None,
+    }
+}
+
+impl KeyFile {
+    const VERSION: i64 = 1;
+
+    // 生成 256 位随机密钥
+    pub fn generate() -> Self {
+        Self {
+            kdf: Kdf::Raw,
+            key: SecretKey::generate(),
+        }
+    }
+
+    // 无法识别为新格式时返回 None，调用方按旧格式处理
+    pub fn parse(content: &[u8]) -> Option<Self> {
+        let mut value: toml::Value = std::str::from_utf8(content).ok()?.parse().ok()?;
+        // 先取出密钥字段，提前返回时也会清零
+        let key_hex = take_secret_field(&mut value, "key");
+        if value.get("version")?.as_integer()? != Self::VERSION {
+            return None;
+        }
+
+        let kdf = Kdf::from_name(value.get("kdf")?.as_str()?).ok()?;
+        let key = SecretKey::from_hex(key_hex.as_deref()?)?;
+        Some(Self { kdf, key })
+    }
+
+    // 读取密钥文件，旧版本的密钥字符串按 SHA-256 派生
+    pub fn load(content: &[u8]) -> Result<Self, CryptoError> {
+        // 受口令保护的密钥文件不能当作旧版本的密钥字符串
+        if ProtectedKeyFile::is_protected(content) {
+            return Err(CryptoError::KeyError("密钥文件受口令保护".to_string()));
+        }
+        if let Some(key_file) = Self::parse(content) {
+            return Ok(key_file);
+        }
+
+        if content.len() < 8 {
+            return Err(CryptoError::KeyError("密钥长度不足".to_string()));
+        }
+        Ok(Self::legacy(content))
+    }
+
+    // 旧版本的密钥：对密钥字符串做一次 SHA-256，用于解密旧仓库中的文件
+    pub fn legacy(passphrase: &[u8]) -> Self {
+        Self {
+            kdf: Kdf::Sha256,
+            key: Encryptor::sha256_key(passphrase),
+        }
+    }
+
+    // 解析从环境变量等外部来源提供的密钥：密钥文件、export-key 导出的文件或 64 位十六进制密钥；
+    // 不接受旧版本的密钥字符串，避免把格式错误的输入当作口令
+    pub fn from_supplied(content: &[u8]) -> Result<Self, CryptoError> {
+        let invalid = || CryptoError::KeyError("密钥格式错误".to_string());
+        let text = std::str::from_utf8(content).map_err(|_| invalid())?.trim();
+        if let Some(key) = SecretKey::from_hex(text) {
+            return Ok(Self { kdf: Kdf::Raw, key });
+        }
+        if let Ok(export) = KeyExport::parse(text) {
+            return Ok(export.key_file);
+        }
+        Self::parse(text.as_bytes()).ok_or_else(invalid)
+    }
+
+    pub fn fingerprint_hex(&self) -> String {
+        hex::encode(Encryptor::key_fingerprint(&self.key))
+    }
+
+    pub fn to_toml(&self) -> Zeroizing<String> {
+        Zeroizing::new(format!(
+            "version = {}\nkdf = \"{}\"\nkey = \"{}\"\n",
+            Self::VERSION,
+            self.kdf.name(),
+            *self.key.to_hex()
+        ))
+    }
+}
+
+// 受口令保护的密钥文件（protect-key），保存在 .git/cryptx/keys 中代替明文密钥
+//
+// 使用 Argon2id 从口令派生包装密钥，再用 AES-256-GCM 加密 KeyFile；
+// 密钥指纹以明文保存，不输入口令也能识别密钥
+pub struct ProtectedKeyFile {
+    pub params: KdfParams,
+    pub fingerprint: String,
+    nonce: [u8; Encryptor::NONCE_SIZE],
+    ciphertext: Vec<u8>,
+}
+
+impl ProtectedKeyFile {
+    const FORMAT: &'static str = "git-cryptx-protected-key";
+    const VERSION: i64 = 1;
+
+    pub fn is_protected(content: &[u8]) -> bool {
+        std::str::from_utf8(content)
+            .ok()
+            .and_then(|c| c.parse::<toml::Value>().ok())
+            .and_then(|v| v.get("format")?.as_str().map(|f| f == Self::FORMAT))
+            .unwrap_or(false)
+    }
+
+    pub fn protect(
+        key_file: &KeyFile,
+        passphrase: &[u8],
+        params: KdfParams,
+    ) -> Result<Self, CryptoError> {
+        let fingerprint = key_file.fingerprint_hex();
+        let mut nonce = [0u8; Encryptor::NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+
+        let cipher = SuiteCipher::new(CipherSuite::Aes256Gcm, &params.derive_key(passphrase)?);
+        let ciphertext = cipher.encrypt(
+            &nonce,
+            Payload {
+                msg: key_file.to_toml().as_bytes(),
+                aad: &Self::aad(&fingerprint),
+            },
+        )?;
+        Ok(Self {
+            params,
+            fingerprint,
+            nonce,
+            ciphertext,
+        })
+    }
+
+    // 口令错误或文件被篡改时返回错误
+    pub fn unprotect(&self, passphrase: &[u8]) -> Result<KeyFile, CryptoError> {
+        let cipher = SuiteCipher::new(CipherSuite::Aes256Gcm, &self.params.derive_key(passphrase)?);
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(
+                    &self.nonce,
+                    Payload {
+                        msg: &self.ciphertext,
+                        aad: &Self::aad(&self.fingerprint),
+                    },
+                )
+                .map_err(|_| CryptoError::KeyError("口令错误".to_string()))?,
+        );
+
+        let key_file = KeyFile::parse(&plaintext)
+            .ok_or_else(|| CryptoError::KeyError("密钥格式错误".to_string()))?;
+        if key_file.fingerprint_hex() != self.fingerprint {
+            return Err(CryptoError::KeyError("密钥指纹不匹配".to_string()));
+        }
+        Ok(key_file)
+    }
+
+    fn aad(fingerprint: &str) -> Vec<u8> {
+        [Self::FORMAT.as_bytes(), fingerprint.as_bytes()].concat()
+    }
+
+    pub fn parse(content: &str) -> Result<Self, CryptoError> {
+        let value: toml::Value = content
+            .parse()
+            .map_err(|e: toml::de::Error| CryptoError::KeyError(e.to_string()))?;
+        let field = |name: &str| {
+            value
+                .get(name)
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| CryptoError::KeyError(format!("缺少字段: {}", name)))
+        };
+
+        if field("format")? != Self::FORMAT {
+            return Err(CryptoError::KeyError(
+                "不是受口令保护的密钥文件".to_string(),
+            ));
+        }
+        if value.get("version").and_then(|v| v.as_integer()) != Some(Self::VERSION) {
+            return Err(CryptoError::KeyError("不支持的密钥文件版本".to_string()));
+        }
+
+        let nonce = hex::decode(field("nonce")?)
+            .ok()
+            .and_then(|v| v.try_into().ok())
+            .ok_or_else(|| CryptoError::KeyError("nonce 格式错误".to_string()))?;
+        let ciphertext = hex::decode(field("ciphertext")?)
+            .map_err(|_| CryptoError::KeyError("ciphertext 格式错误".to_string()))?;
+        Ok(Self {
+            params: KdfParams::parse(content)?,
+            fingerprint: field("fingerprint")?.to_string(),
+            nonce,
+            ciphertext,
+        })
+    }
+
+    pub fn to_toml(&self) -> String {
+        format!(
+            "format = \"{}\"\nversion = {}\nfingerprint = \"{}\"\n{}nonce = \"{}\"\nciphertext = \"{}\"\n",
+            Self::FORMAT,
+            Self::VERSION,
+            self.fingerprint,
+            self.params.to_toml(),
+            hex::encode(self.nonce),
+            hex::encode(&self.ciphertext)
+        )
+    }
+}
+
+// 密文头部
+//
+// v0: GITENC + nonce
+// v1: GITENC + 版本 + nonce
+// v2: GITENC + 版本 + 加密套件 + 密钥派生方式 + 标志位 + 密钥指纹 + nonce
+// v3: GITENC + 版本 + 加密套件 + 密钥派生方式 + 标志位 + 密钥指纹 [+ 路径长度 (u16) + 路径]，
+//     之后是分段密文
+//
+// v2 头部整体作为关联数据参与认证，v3 头部参与每一段的认证
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub suite: CipherSuite,
+    pub kdf: Kdf,
+    // 选项标志位：低两位为压缩方式，PATH_BOUND 表示绑定了路径，
+    // PADDING_BLOCK / PADDING_PADME 表示明文带有填充（仅 v3），其余位为 0
+    pub flags: u8,
+    // v0/v1 格式没有记录密钥指纹
+    pub fingerprint: Option<[u8; Header::FINGERPRINT_SIZE]>,
+    // v3 格式每段有独立的 nonce，头部中为全 0
+    pub nonce: [u8; Encryptor::NONCE_SIZE],
+    // v3 格式绑定的仓库相对路径
+    pub path: Option<String>,
+}
+
+impl Header {
+    pub const V0: u8 = 0;
+    pub const V1: u8 = 1;
+    pub const V2: u8 = 2;
+    pub const V3: u8 = 3;
+    pub const CURRENT_VERSION: u8 = Self::V3;
+    pub const FINGERPRINT_SIZE: usize = 8;
+    // v2 头部中 nonce 之前的部分长度，也是 v3 头部固定部分的长度
+    pub const PREFIX_LEN: usize = Encryptor::MAGIC_HEADER.len() + 4 + Self::FINGERPRINT_SIZE;
+    pub const COMPRESSION_MASK: u8 = 0x03;
+    pub const PATH_BOUND: u8 = 0x04;
+    pub const PADDING_BLOCK: u8 = 0x08;
+    pub const PADDING_PADME: u8 = 0x10;
+    const PADDING_MASK: u8 = Self::PADDING_BLOCK | Self::PADDING_PADME;
+    const FLAGS_OFFSET: usize = Encryptor::MAGIC_HEADER.len() + 3;
+
+    fn new(kdf: Kdf, fingerprint: [u8; Self::FINGERPRINT_SIZE], options: &EncryptOptions) -> Self {
+        Self {
+            version: Self::CURRENT_VERSION,
+            suite: options.suite,
+            kdf,
+            flags: options.flags(),
+            fingerprint: Some(fingerprint),
+            nonce: [0u8; Encryptor::NONCE_SIZE],
+            path: options.path.map(str::to_string),
+        }
+    }
+
+    // head 是完整的 v3 头部固定部分且绑定了路径时，从 reader 读取路径并追加到 head。
+    // 旧格式的 nonce 可能恰好像绑定路径的 v3 头部，因此数据不足时不报错，读到的内容
+    // 全部追加到 head，调用方解析头部失败后仍可以按旧格式整体解密
+    pub fn read_path<R: Read>(head: &mut Vec<u8>, reader: &mut R) -> io::Result<()> {
+        let bound = head.len() == Self::PREFIX_LEN
+            && Encryptor::is_encrypted(head)
+            && head[Encryptor::MAGIC_HEADER.len()] == Self::V3
+            && head[Self::FLAGS_OFFSET] & Self::PATH_BOUND != 0;
+        if bound {
+            let start = head.len();
+            reader.by_ref().take(2).read_to_end(head)?;
+            if head.len() == start + 2 {
+                let len = u16::from_be_bytes([head[start], head[start + 1]]);
+                reader.by_ref().take(len as u64).read_to_end(head)?;
+            }
+        }
+        Ok(())
+    }
+
+    // 按版本字节解析头部，返回头部和剩余的密文
+    //
+    // 旧格式没有版本字节，nonce 的首字节可能恰好等于某个版本号，
+    // 调用方在按版本解密失败时应使用 parse_legacy 重试
+    pub fn parse(data: &[u8]) -> Result<(Self, &[u8]), CryptoError> {
+        if !Encryptor::is_encrypted(data) {
+            return Err(CryptoError::EncryptError("不是加密的数据".to_string()));
+        }
+
+        let body = &data[Encryptor::MAGIC_HEADER.len()..];
+        match body.first() {
+            Some(&Self::V3) if data.len() >= Self::PREFIX_LEN => {
+                let flags = body[3];
+                Compression::from_flags(flags)?;
+                if flags & !(Self::COMPRESSION_MASK | Self::PATH_BOUND | Self::PADDING_MASK) != 0
+                    || flags & Self::PADDING_MASK == Self::PADDING_MASK
+                {
+                    return Err(CryptoError::EncryptError(format!(
+                        "不支持的标志位: {:#04x}",
+                        flags
+                    )));
+                }
+                let mut fingerprint = [0u8; Self::FINGERPRINT_SIZE];
+                fingerprint.copy_from_slice(&body[4..4 + Self::FINGERPRINT_SIZE]);
+                let (path, rest) = if flags & Self::PATH_BOUND != 0 {
+                    let (path, rest) = Self::split_path(&data[Self::PREFIX_LEN..])?;
+                    (Some(path), rest)
+                } else {
+                    (None, &data[Self::PREFIX_LEN..])
+                };
+                Ok((
+                    Self {
+                        version: Self::V3,
+                        suite: CipherSuite::from_u8(body[1])?,
+                        kdf: Kdf::from_u8(body[2])?,
+                        flags,
+                        fingerprint: Some(fingerprint),
+                        nonce: [0u8; Encryptor::NONCE_SIZE],
+                        path,
+                    },
+                    rest,
+                ))
+            }
+            Some(&Self::V2) if data.len() >= Self::PREFIX_LEN + Encryptor::NONCE_SIZE => {
+                let suite = CipherSuite::from_u8(body[1])?;
+                let kdf = Kdf::from_u8(body[2])?;
+                let flags = body[3];
+                let mut fingerprint = [0u8; Self::FINGERPRINT_SIZE];
+                fingerprint.copy_from_slice(&body[4..4 + Self::FINGERPRINT_SIZE]);
+                let rest = &data[Self::PREFIX_LEN..];
+                let (nonce, ciphertext) = Self::split_nonce(rest)?;
+                Ok((
+                    Self {
+                        version: Self::V2,
+                        suite,
+                        kdf,
+                        flags,
+                        fingerprint: Some(fingerprint),
+                        nonce,
+                        path: None,
+                    },
+                    ciphertext,
+                ))
+            }
+            Some(&Self::V1) => {
+                let (nonce, ciphertext) = Self::split_nonce(&body[1..])?;
+                Ok((Self::unversioned(Self::V1, nonce), ciphertext))
+            }
+            _ => Self::parse_legacy(data),
+        }
+    }
+
+    // 按旧格式 (v0) 解析头部
+    pub fn parse_legacy(data: &[u8]) -> Result<(Self, &[u8]), CryptoError> {
+        if !Encryptor::is_encrypted(data) {
+            return Err(CryptoError::EncryptError("不是加密的数据".to_string()));
+        }
+
+        let (nonce, ciphertext) = Self::split_nonce(&data[Encryptor::MAGIC_HEADER.len()..])?;
+        Ok((Self::unversioned(Self::V0, nonce), ciphertext))
+    }
+
+    fn unversioned(version: u8, nonce: [u8; Encryptor::NONCE_SIZE]) -> Self {
+        Self {
+            version,
+            suite: CipherSuite::Aes256Gcm,
+            kdf: Kdf::Sha256,
+            flags: 0,
+            fingerprint: None,
+            nonce,
+            path: None,
+        }
+    }
+
+    fn split_nonce(data: &[u8]) -> Result<([u8; Encryptor::NONCE_SIZE], &[u8]), CryptoError> {
+        if data.len() < Encryptor::NONCE_SIZE {
+            return Err(CryptoError::EncryptError("数据格式错误".to_string()));
+        }
+
+        let (nonce_bytes, ciphertext) = data.split_at(Encryptor::NONCE_SIZE);
+        let mut nonce = [0u8; Encryptor::NONCE_SIZE];
+        nonce.copy_from_slice(nonce_bytes);
+        Ok((nonce, ciphertext))
+    }
+
+    fn split_path(data: &[u8]) -> Result<(String, &[u8]), CryptoError> {
+        let format_error = || CryptoError::EncryptError("数据格式错误".to_string());
+        if data.len() < 2 {
+            return Err(format_error());
+        }
+        let (len, rest) = data.split_at(2);
+        let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+        if rest.len() < len {
+            return Err(format_error());
+        }
+        let (path, rest) = rest.split_at(len);
+        let path = String::from_utf8(path.to_vec()).map_err(|_| format_error())?;
+        Ok((path, rest))
+    }
+
+    // nonce 之前的头部字节，用于派生 nonce
+    fn prefix_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(Self::PREFIX_LEN);
+        output.extend_from_slice(Encryptor::MAGIC_HEADER);
+        match self.version {
+            Self::V0 => {}
+            Self::V1 => output.push(Self::V1),
+            _ => {
+                output.push(self.version);
+                output.push(self.suite as u8);
+                output.push(self.kdf as u8);
+                output.push(self.flags);
+                output.extend_from_slice(&self.fingerprint.unwrap_or_default());
+                if let Some(path) = &self.path {
+                    output.extend_from_slice(&(path.len() as u16).to_be_bytes());
+                    output.extend_from_slice(path.as_bytes());
+                }
+            }
+        }
+        output
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = self.prefix_bytes();
+        if self.version != Self::V3 {
+            output.extend_from_slice(&self.nonce);
+        }
+        output
+    }
+}
+
+// 用于在成员之间传递的自描述密钥文件（export-key / unlock）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyExport {
+    pub key_file: KeyFile,
+    // 创建时间，UTC RFC 3339 格式
+    pub created: String,
+}
+
+impl KeyExport {
+    const FORMAT: &'static str = "git-cryptx-key";
+    const VERSION: i64 = 1;
+
+    pub fn to_toml(&self) -> Zeroizing<String> {
+        Zeroizing::new(format!(
+            "format = \"{}\"\nversion = {}\nkdf = \"{}\"\nkey = \"{}\"\nfingerprint = \"{}\"\ncreated = \"{}\"\n",
+            Self::FORMAT,
+            Self::VERSION,
+            self.key_file.kdf.name(),
+            *self.key_file.key.to_hex(),
+            self.key_file.fingerprint_hex(),
+            self.created
+        ))
+    }
+
+    // 解析密钥文件并校验其中记录的指纹
+    pub fn parse(content: &str) -> Result<Self, CryptoError> {
+        let mut value: toml::Value = content
+            .parse()
+            .map_err(|e: toml::de::Error| CryptoError::KeyError(e.to_string()))?;
+        let key_hex = take_secret_field(&mut value, "key");
+        let field = |name: &str| {
+            value
+                .get(name)
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| CryptoError::KeyError(format!("缺少字段: {}", name)))
+        };
+
+        if field("format")? != Self::FORMAT {
+            return Err(CryptoError::KeyError(
+                "不是 git-cryptx 密钥文件".to_string(),
+            ));
+        }
+        let version = value.get("version").and_then(|v| v.as_integer());
+        if version != Some(Self::VERSION) {
+            return Err(CryptoError::KeyError("不支持的密钥文件版本".to_string()));
+        }
+
+        let key = key_hex
+            .as_deref()
+            .and_then(|hex| SecretKey::from_hex(hex))
+            .ok_or_else(|| CryptoError::KeyError("密钥格式错误".to_string()))?;
+        let key_file = KeyFile {
+            kdf: Kdf::from_name(field("kdf")?)?,
+            key,
+        };
+        if key_file.fingerprint_hex() != field("fingerprint")? {
+            return Err(CryptoError::KeyError("密钥指纹不匹配".to_string()));
+        }
+
+        Ok(Self {
+            key_file,
+            created: field("created")?.to_string(),
+        })
+    }
+}
+
 pub struct Encryptor {
-    cipher: Aes256Gcm,
+    // 旧格式 (v0) 直接使用 SHA-256(密钥) 作为 AES 密钥
+    legacy_cipher: SuiteCipher,
+    // v1 及以后的格式使用由主密钥派生的独立子密钥，每种加密套件一个
+    ciphers: Vec<SuiteCipher>,
+    nonce_key: SecretKey,
+    kdf: Kdf,
+    fingerprint: [u8; Header::FINGERPRINT_SIZE],
 }
 
 impl Encryptor {
     // 魔数标记，用于识别加密文件
-    const MAGIC_HEADER: &'static [u8] = b"GITENC";
-    // nonce 长度（12字节是 AES-GCM 的推荐值）
+    pub const MAGIC_HEADER: &'static [u8] = b"GITENC";
+    // nonce 长度（12字节是 AES-GCM 的推荐值），XChaCha20-Poly1305 使用 24 字节
     const NONCE_SIZE: usize = 12;
+    const MAX_NONCE_SIZE: usize = 24;
+    const TAG_SIZE: usize = 16;
+    // 分段加密时每段明文的长度
+    pub const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+    // 解密第一段所需读取的最大长度：头部、一整段以及判断是否为最后一段的一个字节
+    pub const STREAM_HEAD_LEN: usize =
+        Header::PREFIX_LEN + Self::MAX_NONCE_SIZE + Self::STREAM_CHUNK_SIZE + Self::TAG_SIZE + 1;
+    // 子密钥派生标签，加密子密钥的标签见 CipherSuite::key_label
+    const NONCE_KEY_LABEL: &'static [u8] = b"git-cryptx/v1/nonce";
+    const FINGERPRINT_LABEL: &'static [u8] = b"git-cryptx/fingerprint";
 
     pub fn is_encrypted(content: &[u8]) -> bool {
         content.starts_with(Self::MAGIC_HEADER)
@@ -35,51 +1029,150 @@ impl Encryptor {
         }
 
         // 使用 SHA-256 扩展密钥到32字节
-        let mut hasher = Sha256::new();
-        hasher.update(key);
-        let key = hasher.finalize();
+        Self::from_key(&Self::sha256_key(key), Kdf::Sha256)
+    }
 
-        let cipher =
-            Aes256Gcm::new_from_slice(&key).map_err(|e| CryptoError::KeyError(e.to_string()))?;
+    // 旧版本的密钥派生：SHA-256(密钥)，摘要的中间结果会被清零
+    fn sha256_key(key: &[u8]) -> SecretKey {
+        let mut digest = Sha256::digest(key);
+        let key = SecretKey::from_slice(&digest).expect("SHA-256 摘要为 32 字节");
+        digest.as_mut_slice().zeroize();
+        key
+    }
+
+    // 使用已派生好的 32 字节密钥
+    pub fn from_key(key: &SecretKey, kdf: Kdf) -> Result<Self, CryptoError> {
+        let legacy_cipher = SuiteCipher::new(CipherSuite::Aes256Gcm, key);
+
+        // 加密与 nonce 派生使用不同的子密钥，避免同一密钥用于两种用途
+        let ciphers = CipherSuite::ALL
+            .iter()
+            .map(|suite| SuiteCipher::new(*suite, &Self::derive_subkey(key, suite.key_label())))
+            .collect();
+        let nonce_key = Self::derive_subkey(key, Self::NONCE_KEY_LABEL);
+
+        Ok(Self {
+            legacy_cipher,
+            ciphers,
+            nonce_key,
+            kdf,
+            fingerprint: Self::key_fingerprint(key),
+        })
+    }
+
+    // 由 32 字节密钥计算指纹
+    pub fn key_fingerprint(key: &SecretKey) -> [u8; Header::FINGERPRINT_SIZE] {
+        let mut fingerprint = [0u8; Header::FINGERPRINT_SIZE];
+        fingerprint.copy_from_slice(
+            &Self::derive_subkey(key, Self::FINGERPRINT_LABEL).expose()[..Header::FINGERPRINT_SIZE],
+        );
+        fingerprint
+    }
 
-        Ok(Self { cipher })
+    // 根据密钥文件内容创建加密器，兼容旧版本直接保存的密钥字符串
+    pub fn from_key_file(content: &[u8]) -> Result<Self, CryptoError> {
+        let key_file = KeyFile::load(content)?;
+        Self::from_key(&key_file.key, key_file.kdf)
     }
 
-    // 新增：根据文件内容生成确定性nonce
-    fn generate_deterministic_nonce(&self, data: &[u8]) -> [u8; Self::NONCE_SIZE] {
-        let mut hasher = Sha256::new();
-        hasher.update(data);
-        let hash = hasher.finalize();
-        
-        // 使用哈希的前12字节作为nonce
-        let mut nonce = [0u8; Self::NONCE_SIZE];
-        nonce.copy_from_slice(&hash[..Self::NONCE_SIZE]);
-        nonce
+    // 密钥指纹，可以公开，用于识别密文由哪个密钥加密
+    pub fn fingerprint(&self) -> [u8; Header::FINGERPRINT_SIZE] {
+        self.fingerprint
+    }
+
+    pub fn fingerprint_hex(&self) -> String {
+        hex::encode(self.fingerprint)
+    }
+
+    fn cipher(&self, suite: CipherSuite) -> &SuiteCipher {
+        self.ciphers
+            .iter()
+            .find(|cipher| cipher.suite() == suite)
+            .expect("每种加密套件都有子密钥")
+    }
+
+    fn derive_subkey(key: &SecretKey, label: &[u8]) -> SecretKey {
+        let mut mac =
+            <HmacSha256 as Mac>::new_from_slice(key.expose()).expect("HMAC 接受任意长度的密钥");
+        mac.update(label);
+        let mut output = mac.finalize().into_bytes();
+        let subkey = SecretKey::from_slice(&output).expect("HMAC-SHA256 输出为 32 字节");
+        output.as_mut_slice().zeroize();
+        subkey
+    }
+
+    // 使用带密钥的 PRF (HMAC-SHA256) 根据头部和文件内容生成确定性 nonce，
+    // 没有密钥的人无法通过对猜测的明文求哈希来验证猜测
+    fn generate_deterministic_nonce(&self, prefix: &[u8], data: &[u8], size: usize) -> Vec<u8> {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(self.nonce_key.expose())
+            .expect("HMAC 接受任意长度的密钥");
+        mac.update(&(prefix.len() as u64).to_be_bytes());
+        mac.update(prefix);
+        mac.update(data);
+        let tag = mac.finalize().into_bytes();
+
+        // 使用 PRF 输出的前 size 字节作为nonce
+        tag[..size].to_vec()
     }
 
     pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
-        // 使用确定性nonce替代随机nonce
-        let nonce_bytes = self.generate_deterministic_nonce(data);
-        let nonce = Nonce::from_slice(&nonce_bytes);
+        self.encrypt_with(data, &EncryptOptions::default())
+    }
 
-        // 计算输出大小：魔数 + nonce + 加密数据
-        let mut output =
-            Vec::with_capacity(Self::MAGIC_HEADER.len() + Self::NONCE_SIZE + data.len() + 16);
+    pub fn encrypt_with(
+        &self,
+        data: &[u8],
+        options: &EncryptOptions,
+    ) -> Result<Vec<u8>, CryptoError> {
+        // 计算输出大小（不压缩时）：头部 + 每段的 nonce 和认证标签 + 加密数据
+        let segments = data.len() / Self::STREAM_CHUNK_SIZE + 1;
+        let capacity =
+            Header::PREFIX_LEN + segments * (Self::NONCE_SIZE + Self::TAG_SIZE) + data.len();
 
-        // 写入魔数
-        output.extend_from_slice(Self::MAGIC_HEADER);
-        // 写入 nonce
-        output.extend_from_slice(&nonce_bytes);
+        let mut writer = self.encrypt_writer(Vec::with_capacity(capacity), options)?;
+        writer.write_all(data)?;
+        Ok(writer.finish()?)
+    }
 
-        // 加密数据
-        let ciphertext = self
-            .cipher
-            .encrypt(nonce, data)
-            .map_err(|e| CryptoError::EncryptError(e.to_string()))?;
+    // 分段加密写入器，写完后必须调用 finish 写出最后一段
+    pub fn encrypt_writer<W: Write>(
+        &self,
+        inner: W,
+        options: &EncryptOptions,
+    ) -> io::Result<EncryptWriter<'_, W>> {
+        EncryptWriter::new(self, inner, options)
+    }
 
-        // 写入加密数据
-        output.extend(ciphertext);
-        Ok(output)
+    // 读取分段格式 (v3) 的头部，返回逐段解密的读取器
+    pub fn decrypt_reader<R: Read>(
+        &self,
+        mut inner: R,
+    ) -> Result<DecryptReader<'_, R>, CryptoError> {
+        let mut prefix = vec![0u8; Header::PREFIX_LEN];
+        inner.read_exact(&mut prefix)?;
+        Header::read_path(&mut prefix, &mut inner)?;
+        let (header, _) = Header::parse(&prefix)?;
+        if header.version != Header::V3 {
+            return Err(CryptoError::EncryptError("不是分段加密的数据".to_string()));
+        }
+        if header.fingerprint != Some(self.fingerprint) {
+            return Err(CryptoError::KeyError("密钥指纹不匹配".to_string()));
+        }
+
+        let compression = Compression::from_flags(header.flags)?;
+        let padded = header.flags & Header::PADDING_MASK != 0;
+        let segments = SegmentReader::new(self, header.suite, prefix, padded, inner);
+        Ok(DecryptReader::new(segments, compression)?)
+    }
+
+    // 分段的关联数据：头部 + 段序号 + 是否最后一段 + 上一段的认证标签
+    fn segment_aad(prefix: &[u8], index: u64, last: bool, prev_tag: &[u8]) -> Vec<u8> {
+        let mut aad = Vec::with_capacity(prefix.len() + 9 + prev_tag.len());
+        aad.extend_from_slice(prefix);
+        aad.extend_from_slice(&index.to_be_bytes());
+        aad.push(last as u8);
+        aad.extend_from_slice(prev_tag);
+        aad
     }
 
     pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
@@ -88,19 +1181,517 @@ impl Encryptor {
             return Err(CryptoError::EncryptError("不是加密的数据".to_string()));
         }
 
-        // 提取 nonce 和加密数据
-        let data = &data[Self::MAGIC_HEADER.len()..];
-        if data.len() < Self::NONCE_SIZE {
-            return Err(CryptoError::EncryptError("数据格式错误".to_string()));
+        let result = match Header::parse(data) {
+            Ok((header, _)) if header.version == Header::V3 => self.decrypt_v3(data),
+            Ok((header, ciphertext)) => match header.version {
+                Header::V2 => self.decrypt_v2(&header, ciphertext),
+                Header::V1 => self.cipher(header.suite).decrypt(&header.nonce, ciphertext),
+                _ => return self.decrypt_v0(data),
+            },
+            Err(e) => Err(e),
+        };
+
+        // 版本化解密失败时回退到旧格式
+        result.or_else(|e| self.decrypt_v0(data).map_err(|_| e))
+    }
+
+    fn decrypt_v2(&self, header: &Header, ciphertext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        if header.fingerprint != Some(self.fingerprint) {
+            return Err(CryptoError::KeyError("密钥指纹不匹配".to_string()));
+        }
+
+        self.cipher(CipherSuite::Aes256Gcm).decrypt(
+            &header.nonce,
+            Payload {
+                msg: ciphertext,
+                aad: &header.to_bytes(),
+            },
+        )
+    }
+
+    fn decrypt_v3(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let mut output = Vec::with_capacity(data.len());
+        self.decrypt_reader(data)?
+            .read_to_end(&mut output)
+            .map_err(|e| CryptoError::EncryptError(e.to_string()))?;
+        Ok(output)
+    }
+
+    // 旧格式：GITENC + nonce + 密文，nonce 为明文的 SHA-256
+    fn decrypt_v0(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let (header, ciphertext) = Header::parse_legacy(data)?;
+        self.legacy_cipher.decrypt(&header.nonce, ciphertext)
+    }
+}
+
+// 分段加密 (v3)
+//
+// 明文按 STREAM_CHUNK_SIZE 分段，每段写出 nonce + 密文 + 认证标签。每段的关联数据包含
+// 头部、段序号、是否最后一段以及上一段的认证标签，因此分段不能被重排、删除或截断；
+// nonce 由关联数据和该段明文确定性生成，相同内容的加密结果保持不变
+struct SegmentWriter<'a, W: Write> {
+    encryptor: &'a Encryptor,
+    suite: CipherSuite,
+    inner: W,
+    prefix: Vec<u8>,
+    buffer: Vec<u8>,
+    index: u64,
+    prev_tag: [u8; Encryptor::TAG_SIZE],
+    padding: Padding,
+    // 已写入的明文长度，用于计算填充
+    length: u64,
+}
+
+impl<'a, W: Write> SegmentWriter<'a, W> {
+    fn new(encryptor: &'a Encryptor, mut inner: W, options: &EncryptOptions) -> io::Result<Self> {
+        if options
+            .path
+            .is_some_and(|path| path.len() > u16::MAX as usize)
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "路径过长"));
+        }
+        let prefix = Header::new(encryptor.kdf, encryptor.fingerprint, options).prefix_bytes();
+        inner.write_all(&prefix)?;
+        Ok(Self {
+            encryptor,
+            suite: options.suite,
+            inner,
+            prefix,
+            buffer: Vec::with_capacity(Encryptor::STREAM_CHUNK_SIZE),
+            index: 0,
+            prev_tag: [0u8; Encryptor::TAG_SIZE],
+            padding: options.padding,
+            length: 0,
+        })
+    }
+
+    fn write_segment(&mut self, last: bool) -> io::Result<()> {
+        let aad = Encryptor::segment_aad(&self.prefix, self.index, last, &self.prev_tag);
+        let nonce = self.encryptor.generate_deterministic_nonce(
+            &aad,
+            &self.buffer,
+            self.suite.nonce_size(),
+        );
+        let ciphertext = self
+            .encryptor
+            .cipher(self.suite)
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &self.buffer,
+                    aad: &aad,
+                },
+            )
+            .map_err(io::Error::other)?;
+
+        self.inner.write_all(&nonce)?;
+        self.inner.write_all(&ciphertext)?;
+        self.prev_tag
+            .copy_from_slice(&ciphertext[ciphertext.len() - Encryptor::TAG_SIZE..]);
+        self.index += 1;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    // 追加填充后写出最后一段（可能为空），返回内部的写入器
+    fn finish(mut self) -> io::Result<W> {
+        if self.padding != Padding::None {
+            let padded = self.padding.padded_len(self.length + 1);
+            self.write_all(&[Padding::MARKER])?;
+            let zeros = [0u8; 4096];
+            let mut remaining = padded - self.length;
+            while remaining > 0 {
+                let len = remaining.min(zeros.len() as u64) as usize;
+                self.write_all(&zeros[..len])?;
+                remaining -= len as u64;
+            }
+        }
+        self.write_segment(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for SegmentWriter<'_, W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        // 缓冲区满且还有后续数据时，才能确定这一段不是最后一段
+        if self.buffer.len() == Encryptor::STREAM_CHUNK_SIZE && !data.is_empty() {
+            self.write_segment(false)?;
+        }
+        let len = data
+            .len()
+            .min(Encryptor::STREAM_CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..len]);
+        self.length += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// 加密写入器：按选项压缩明文后分段加密，写完后必须调用 finish
+pub struct EncryptWriter<'a, W: Write> {
+    stage: WriteStage<'a, W>,
+}
+
+enum WriteStage<'a, W: Write> {
+    Plain(SegmentWriter<'a, W>),
+    Deflate(DeflateEncoder<SegmentWriter<'a, W>>),
+    Zstd(zstd::stream::write::Encoder<'static, SegmentWriter<'a, W>>),
+}
+
+impl<'a, W: Write> EncryptWriter<'a, W> {
+    fn new(encryptor: &'a Encryptor, inner: W, options: &EncryptOptions) -> io::Result<Self> {
+        let segments = SegmentWriter::new(encryptor, inner, options)?;
+        let stage = match options.compression {
+            Compression::None => WriteStage::Plain(segments),
+            Compression::Deflate => WriteStage::Deflate(DeflateEncoder::new(
+                segments,
+                flate2::Compression::new(Compression::DEFLATE_LEVEL),
+            )),
+            Compression::Zstd => WriteStage::Zstd(zstd::stream::write::Encoder::new(
+                segments,
+                Compression::ZSTD_LEVEL,
+            )?),
+        };
+        Ok(Self { stage })
+    }
+
+    // 结束压缩并写出最后一段（可能为空），返回内部的写入器
+    pub fn finish(self) -> io::Result<W> {
+        let segments = match self.stage {
+            WriteStage::Plain(segments) => segments,
+            WriteStage::Deflate(encoder) => encoder.finish()?,
+            WriteStage::Zstd(encoder) => encoder.finish()?,
+        };
+        segments.finish()
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<'_, W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match &mut self.stage {
+            WriteStage::Plain(segments) => segments.write(data),
+            WriteStage::Deflate(encoder) => encoder.write(data),
+            WriteStage::Zstd(encoder) => encoder.write(data),
+        }
+    }
+
+    // 只刷新内部的写入器，不强制输出压缩器缓冲的数据，否则相同内容的密文会不同
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.stage {
+            WriteStage::Plain(segments) => segments.flush(),
+            WriteStage::Deflate(encoder) => encoder.get_mut().flush(),
+            WriteStage::Zstd(encoder) => encoder.get_mut().flush(),
+        }
+    }
+}
+
+// 逐段解密 v3 密文，每次只在内存中保存一段
+//
+// 只按需要的长度读取内部读取器，不会多读后续数据
+struct SegmentReader<'a, R: Read> {
+    encryptor: &'a Encryptor,
+    suite: CipherSuite,
+    inner: R,
+    prefix: Vec<u8>,
+    index: u64,
+    prev_tag: [u8; Encryptor::TAG_SIZE],
+    // 为判断是否为最后一段而预读的下一段首字节
+    lookahead: Option<u8>,
+    plaintext: Vec<u8>,
+    position: usize,
+    finished: bool,
+    padded: bool,
+    // 可能是填充的末尾部分：0x80 之后的 0 字节个数，只记录个数不缓存数据
+    held_zeros: Option<u64>,
+    // 确认不是填充后，需要在 plaintext 之前输出的 0x80 和 0 字节
+    pending_marker: bool,
+    pending_zeros: u64,
+}
+
+impl<'a, R: Read> SegmentReader<'a, R> {
+    fn new(
+        encryptor: &'a Encryptor,
+        suite: CipherSuite,
+        prefix: Vec<u8>,
+        padded: bool,
+        inner: R,
+    ) -> Self {
+        Self {
+            encryptor,
+            suite,
+            inner,
+            prefix,
+            index: 0,
+            prev_tag: [0u8; Encryptor::TAG_SIZE],
+            lookahead: None,
+            plaintext: Vec::new(),
+            position: 0,
+            finished: false,
+            padded,
+            held_zeros: None,
+            pending_marker: false,
+            pending_zeros: 0,
+        }
+    }
+
+    // 暂缓输出末尾的 0x80 和其后的 0 字节，直到确认它们是否为填充
+    fn strip_padding(&mut self) -> Result<(), CryptoError> {
+        if let Some(zeros) = self.held_zeros {
+            if self.plaintext.iter().all(|&b| b == 0) {
+                self.held_zeros = Some(zeros + self.plaintext.len() as u64);
+                self.plaintext.clear();
+            } else {
+                self.held_zeros = None;
+                self.pending_marker = true;
+                self.pending_zeros = zeros;
+            }
+        }
+        if self.held_zeros.is_none() {
+            let zeros = self.plaintext.iter().rev().take_while(|&&b| b == 0).count();
+            let end = self.plaintext.len() - zeros;
+            if end > 0 && self.plaintext[end - 1] == Padding::MARKER {
+                self.held_zeros = Some(zeros as u64);
+                self.plaintext.truncate(end - 1);
+            }
+        }
+        if self.finished && self.held_zeros.is_none() {
+            return Err(CryptoError::EncryptError("填充格式错误".to_string()));
+        }
+        Ok(())
+    }
+
+    fn read_segment(&mut self) -> Result<(), CryptoError> {
+        let segment_size = self.suite.segment_size();
+        let nonce_size = self.suite.nonce_size();
+        let mut segment = Vec::with_capacity(segment_size);
+        segment.extend(self.lookahead.take());
+        let remaining = (segment_size - segment.len()) as u64;
+        (&mut self.inner)
+            .take(remaining)
+            .read_to_end(&mut segment)?;
+
+        // 不足一整段，或一整段之后没有更多数据，就是最后一段
+        let last = if segment.len() < segment_size {
+            true
+        } else {
+            let mut next = Vec::with_capacity(1);
+            (&mut self.inner).take(1).read_to_end(&mut next)?;
+            self.lookahead = next.first().copied();
+            self.lookahead.is_none()
+        };
+        if segment.len() < nonce_size + Encryptor::TAG_SIZE {
+            return Err(CryptoError::EncryptError("密文被截断".to_string()));
+        }
+
+        let (nonce, ciphertext) = segment.split_at(nonce_size);
+        let aad = Encryptor::segment_aad(&self.prefix, self.index, last, &self.prev_tag);
+        self.plaintext = self.encryptor.cipher(self.suite).decrypt(
+            nonce,
+            Payload {
+                msg: ciphertext,
+                aad: &aad,
+            },
+        )?;
+        self.position = 0;
+        self.prev_tag
+            .copy_from_slice(&ciphertext[ciphertext.len() - Encryptor::TAG_SIZE..]);
+        self.index += 1;
+        self.finished = last;
+        if self.padded {
+            self.strip_padding()?;
+        }
+        Ok(())
+    }
+
+    fn verify_first_segment(&mut self) -> Result<(), CryptoError> {
+        if self.index == 0 {
+            self.read_segment()?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for SegmentReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            // 先输出确认不是填充的 0x80 和 0 字节
+            if self.pending_marker {
+                self.pending_marker = false;
+                buf[0] = Padding::MARKER;
+                return Ok(1);
+            }
+            if self.pending_zeros > 0 {
+                let len = (buf.len() as u64).min(self.pending_zeros) as usize;
+                buf[..len].fill(0);
+                self.pending_zeros -= len as u64;
+                return Ok(len);
+            }
+            if self.position < self.plaintext.len() {
+                break;
+            }
+            if self.finished {
+                return Ok(0);
+            }
+            self.read_segment()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
         }
 
-        let (nonce_bytes, ciphertext) = data.split_at(Self::NONCE_SIZE);
-        let nonce = Nonce::from_slice(nonce_bytes);
+        let len = buf.len().min(self.plaintext.len() - self.position);
+        buf[..len].copy_from_slice(&self.plaintext[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+// 解密读取器：逐段解密后按头部记录的压缩方式解压
+pub struct DecryptReader<'a, R: Read> {
+    stage: ReadStage<'a, R>,
+}
+
+enum ReadStage<'a, R: Read> {
+    Plain(SegmentReader<'a, R>),
+    Deflate(DeflateDecoder<io::BufReader<SegmentReader<'a, R>>>),
+    Zstd(zstd::stream::read::Decoder<'static, io::BufReader<SegmentReader<'a, R>>>),
+}
+
+impl<'a, R: Read> DecryptReader<'a, R> {
+    fn new(segments: SegmentReader<'a, R>, compression: Compression) -> io::Result<Self> {
+        let stage = match compression {
+            Compression::None => ReadStage::Plain(segments),
+            Compression::Deflate => {
+                ReadStage::Deflate(DeflateDecoder::new(io::BufReader::new(segments)))
+            }
+            Compression::Zstd => ReadStage::Zstd(
+                zstd::stream::read::Decoder::with_buffer(io::BufReader::new(segments))?
+                    .single_frame(),
+            ),
+        };
+        Ok(Self { stage })
+    }
+
+    // 解密并认证第一段。还没有读取任何明文时失败，调用方可以安全地改为输出原始密文
+    pub fn verify_first_segment(&mut self) -> Result<(), CryptoError> {
+        let segments = match &mut self.stage {
+            ReadStage::Plain(segments) => segments,
+            ReadStage::Deflate(decoder) => decoder.get_mut().get_mut(),
+            ReadStage::Zstd(decoder) => decoder.get_mut().get_mut(),
+        };
+        segments.verify_first_segment()
+    }
+}
+
+impl<R: Read> Read for DecryptReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (len, rest) = match &mut self.stage {
+            ReadStage::Plain(segments) => return segments.read(buf),
+            ReadStage::Deflate(decoder) => (decoder.read(buf)?, decoder.get_mut()),
+            ReadStage::Zstd(decoder) => (decoder.read(buf)?, decoder.get_mut()),
+        };
 
-        // 解密数据
-        self.cipher
-            .decrypt(nonce, ciphertext)
-            .map_err(|e| CryptoError::EncryptError(e.to_string()))
+        // 压缩数据结束后读到末尾，确保认证了最后一段且没有多余的内容
+        if len == 0 && !buf.is_empty() && !rest.fill_buf()?.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                CryptoError::EncryptError("压缩数据之后有多余的内容".to_string()),
+            ));
+        }
+        Ok(len)
+    }
+}
+
+// 密钥环：当前密钥用于加密，轮换后保留的旧密钥只用于解密历史提交
+pub struct Keyring {
+    current: Encryptor,
+    retired: Vec<Encryptor>,
+}
+
+impl Keyring {
+    pub fn new(current: Encryptor) -> Self {
+        Self {
+            current,
+            retired: Vec::new(),
+        }
+    }
+
+    pub fn add_retired(&mut self, encryptor: Encryptor) {
+        self.retired.push(encryptor);
+    }
+
+    pub fn current(&self) -> &Encryptor {
+        &self.current
+    }
+
+    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        self.current.encrypt(data)
+    }
+
+    // 按密钥指纹查找密钥
+    pub fn find(&self, fingerprint: &[u8; Header::FINGERPRINT_SIZE]) -> Option<&Encryptor> {
+        std::iter::once(&self.current)
+            .chain(self.retired.iter())
+            .find(|k| &k.fingerprint() == fingerprint)
+    }
+
+    // 分段格式逐段解密；其他格式以及找不到密钥时读入内存后整体解密
+    pub fn decrypt_reader<'a, R: Read + 'a>(
+        &'a self,
+        mut inner: R,
+    ) -> Result<Box<dyn Read + 'a>, CryptoError> {
+        let mut head = Vec::with_capacity(Header::PREFIX_LEN);
+        (&mut inner)
+            .take(Header::PREFIX_LEN as u64)
+            .read_to_end(&mut head)?;
+        Header::read_path(&mut head, &mut inner)?;
+
+        if let Some(header) = Header::parse(&head).ok().map(|(header, _)| header) {
+            let encryptor = header.fingerprint.and_then(|fp| self.find(&fp));
+            if let (Header::V3, Some(encryptor)) = (header.version, encryptor) {
+                let reader = encryptor.decrypt_reader(io::Cursor::new(head).chain(inner))?;
+                return Ok(Box::new(reader));
+            }
+        }
+
+        inner.read_to_end(&mut head)?;
+        Ok(Box::new(io::Cursor::new(self.decrypt(&head)?)))
+    }
+
+    // 按头部中的密钥指纹选择密钥；旧格式没有指纹，依次尝试所有密钥
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let mut keys = std::iter::once(&self.current).chain(self.retired.iter());
+        match Header::parse(data) {
+            Ok((header, _)) if header.fingerprint.is_some() => {
+                match keys.find(|k| header.fingerprint == Some(k.fingerprint())) {
+                    Some(encryptor) => encryptor.decrypt(data),
+                    // 没有匹配的指纹时仍可能是 nonce 恰好像新格式头部的旧格式，依次尝试所有密钥
+                    None => std::iter::once(&self.current)
+                        .chain(self.retired.iter())
+                        .find_map(|k| k.decrypt(data).ok())
+                        .ok_or_else(|| {
+                            CryptoError::KeyError(format!(
+                                "没有指纹为 {} 的密钥",
+                                hex::encode(header.fingerprint.unwrap_or_default())
+                            ))
+                        }),
+                }
+            }
+            _ => {
+                let mut last_error = None;
+                for encryptor in keys {
+                    match encryptor.decrypt(data) {
+                        Ok(plaintext) => return Ok(plaintext),
+                        Err(e) => last_error = Some(e),
+                    }
+                }
+                Err(last_error.expect("密钥环至少包含当前密钥"))
+            }
+        }
     }
 }
 
@@ -126,18 +1717,26 @@ mod tests {
 
     #[test]
     fn test_different_nonce() {
-        let key = b"test-key-12345";
         let data = b"Hello, World!";
-        let encryptor = Encryptor::new(key).unwrap();
+        let encryptor1 = Encryptor::new(b"test-key-12345").unwrap();
+        let encryptor2 = Encryptor::new(b"test-key-67890").unwrap();
 
-        // 两次加密应该产生不同的密文
-        let encrypted1 = encryptor.encrypt(data).unwrap();
-        let encrypted2 = encryptor.encrypt(data).unwrap();
+        // 不同密钥对相同数据应该产生不同的 nonce 和密文
+        let encrypted1 = encryptor1.encrypt(data).unwrap();
+        let encrypted2 = encryptor2.encrypt(data).unwrap();
         assert_ne!(encrypted1, encrypted2);
 
+        // nonce 不能是明文的无密钥哈希
+        let hash = Sha256::digest(data);
+        let (_, segments) = Header::parse(&encrypted1).unwrap();
+        assert_ne!(
+            &segments[..Encryptor::NONCE_SIZE],
+            &hash[..Encryptor::NONCE_SIZE]
+        );
+
         // 但解密后应该得到相同的明文
-        let decrypted1 = encryptor.decrypt(&encrypted1).unwrap();
-        let decrypted2 = encryptor.decrypt(&encrypted2).unwrap();
+        let decrypted1 = encryptor1.decrypt(&encrypted1).unwrap();
+        let decrypted2 = encryptor2.decrypt(&encrypted2).unwrap();
         assert_eq!(decrypted1, decrypted2);
         assert_eq!(decrypted1, data);
     }
@@ -157,4 +1756,562 @@ mod tests {
         let decrypted = encryptor.decrypt(&encrypted1).unwrap();
         assert_eq!(decrypted, data);
     }
+
+    #[test]
+    fn test_legacy_v0_decryption() {
+        let key = b"test-key-12345";
+        let data = b"Hello, World!";
+
+        // 按旧格式构造密文：GITENC + SHA-256(明文)[..12] + 密文
+        let cipher = Aes256Gcm::new_from_slice(&Sha256::digest(key)).unwrap();
+        let hash = Sha256::digest(data);
+        let nonce_bytes = &hash[..Encryptor::NONCE_SIZE];
+        let mut legacy = Encryptor::MAGIC_HEADER.to_vec();
+        legacy.extend_from_slice(nonce_bytes);
+        legacy.extend(
+            cipher
+                .encrypt(GenericArray::from_slice(nonce_bytes), &data[..])
+                .unwrap(),
+        );
+
+        let encryptor = Encryptor::new(key).unwrap();
+        assert_eq!(encryptor.decrypt(&legacy).unwrap(), data);
+    }
+
+    #[test]
+    fn test_header_roundtrip() {
+        let encryptor = Encryptor::new(b"test-key-12345").unwrap();
+        let encrypted = encryptor.encrypt(b"Hello, World!").unwrap();
+
+        let (header, ciphertext) = Header::parse(&encrypted).unwrap();
+        assert_eq!(header.version, Header::CURRENT_VERSION);
+        assert_eq!(header.suite, CipherSuite::Aes256Gcm);
+        assert_eq!(header.kdf, Kdf::Sha256);
+        assert_eq!(header.fingerprint, Some(encryptor.fingerprint()));
+        assert_eq!(header.to_bytes().len() + ciphertext.len(), encrypted.len());
+        assert_eq!(
+            &encrypted[..header.to_bytes().len()],
+            &header.to_bytes()[..]
+        );
+    }
+
+    #[test]
+    fn test_tampered_header_rejected() {
+        let encryptor = Encryptor::new(b"test-key-12345").unwrap();
+        let mut encrypted = encryptor.encrypt(b"Hello, World!").unwrap();
+
+        // 头部参与认证，修改标志位后应当解密失败
+        encrypted[Encryptor::MAGIC_HEADER.len() + 3] ^= 0x01;
+        assert!(encryptor.decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn test_fingerprint_mismatch() {
+        let encryptor1 = Encryptor::new(b"test-key-12345").unwrap();
+        let encryptor2 = Encryptor::new(b"test-key-67890").unwrap();
+        assert_ne!(encryptor1.fingerprint(), encryptor2.fingerprint());
+
+        let encrypted = encryptor1.encrypt(b"Hello, World!").unwrap();
+        assert!(matches!(
+            encryptor2.decrypt(&encrypted),
+            Err(CryptoError::KeyError(_))
+        ));
+    }
+
+    #[test]
+    fn test_legacy_v1_decryption() {
+        let key = b"test-key-12345";
+        let data = b"Hello, World!";
+        let encryptor = Encryptor::new(key).unwrap();
+
+        // 按 v1 格式构造密文：GITENC + 1 + nonce + 密文
+        let nonce = [7u8; Encryptor::NONCE_SIZE];
+        let mut legacy = Encryptor::MAGIC_HEADER.to_vec();
+        legacy.push(Header::V1);
+        legacy.extend_from_slice(&nonce);
+        legacy.extend(
+            encryptor
+                .cipher(CipherSuite::Aes256Gcm)
+                .encrypt(&nonce, &data[..])
+                .unwrap(),
+        );
+
+        assert_eq!(encryptor.decrypt(&legacy).unwrap(), data);
+    }
+
+    #[test]
+    fn test_legacy_v2_decryption() {
+        let data = b"Hello, World!";
+        let encryptor = Encryptor::new(b"test-key-12345").unwrap();
+
+        // 按 v2 格式构造密文：整体加密，头部（含 nonce）作为关联数据
+        let mut header = Header::new(
+            Kdf::Sha256,
+            encryptor.fingerprint(),
+            &EncryptOptions::default(),
+        );
+        header.version = Header::V2;
+        let nonce = encryptor.generate_deterministic_nonce(
+            &header.prefix_bytes(),
+            data,
+            Encryptor::NONCE_SIZE,
+        );
+        header.nonce.copy_from_slice(&nonce);
+        let mut legacy = header.to_bytes();
+        let ciphertext = encryptor
+            .cipher(CipherSuite::Aes256Gcm)
+            .encrypt(
+                &header.nonce,
+                Payload {
+                    msg: data,
+                    aad: &legacy,
+                },
+            )
+            .unwrap();
+        legacy.extend(ciphertext);
+
+        assert_eq!(encryptor.decrypt(&legacy).unwrap(), data);
+    }
+
+    #[test]
+    fn test_stream_roundtrip() {
+        let encryptor = Encryptor::new(b"test-key-12345").unwrap();
+        let chunk = Encryptor::STREAM_CHUNK_SIZE;
+
+        for len in [0, 1, chunk - 1, chunk, chunk + 1, 3 * chunk + 7] {
+            let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+
+            // 分多次小块写入与整体加密的结果相同
+            let mut writer = encryptor
+                .encrypt_writer(Vec::new(), &EncryptOptions::default())
+                .unwrap();
+            for piece in data.chunks(1000) {
+                writer.write_all(piece).unwrap();
+            }
+            let encrypted = writer.finish().unwrap();
+            assert_eq!(encrypted, encryptor.encrypt(&data).unwrap());
+
+            let mut decrypted = Vec::new();
+            encryptor
+                .decrypt_reader(&encrypted[..])
+                .unwrap()
+                .read_to_end(&mut decrypted)
+                .unwrap();
+            assert_eq!(decrypted, data);
+            assert_eq!(encryptor.decrypt(&encrypted).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_stream_segments_authenticated() {
+        let encryptor = Encryptor::new(b"test-key-12345").unwrap();
+        let data = vec![42u8; Encryptor::STREAM_CHUNK_SIZE * 2 + 10];
+        let encrypted = encryptor.encrypt(&data).unwrap();
+        let segment = CipherSuite::Aes256Gcm.segment_size();
+        let first = Header::PREFIX_LEN;
+
+        // 截断最后一段
+        assert!(encryptor
+            .decrypt(&encrypted[..first + 2 * segment])
+            .is_err());
+        // 截断到整段边界之前，第一段不是最后一段
+        assert!(encryptor.decrypt(&encrypted[..first + segment]).is_err());
+
+        // 交换前两段（内容相同的明文）
+        let mut swapped = encrypted[..first].to_vec();
+        swapped.extend_from_slice(&encrypted[first + segment..first + 2 * segment]);
+        swapped.extend_from_slice(&encrypted[first..first + segment]);
+        swapped.extend_from_slice(&encrypted[first + 2 * segment..]);
+        assert!(encryptor.decrypt(&swapped).is_err());
+    }
+
+    #[test]
+    fn test_compressed_roundtrip() {
+        let encryptor = Encryptor::new(b"test-key-12345").unwrap();
+        let data: Vec<u8> = (0..20000)
+            .flat_map(|i| format!("{{\"id\": {}, \"name\": \"fixture\"}}\n", i % 100).into_bytes())
+            .collect();
+
+        for compression in [Compression::Deflate, Compression::Zstd] {
+            let options = EncryptOptions {
+                compression,
+                ..Default::default()
+            };
+            let encrypted = encryptor.encrypt_with(&data, &options).unwrap();
+            assert!(encrypted.len() < data.len() / 10);
+            assert_eq!(encrypted, encryptor.encrypt_with(&data, &options).unwrap());
+
+            let (header, _) = Header::parse(&encrypted).unwrap();
+            assert_eq!(Compression::from_flags(header.flags).unwrap(), compression);
+            assert_eq!(encryptor.decrypt(&encrypted).unwrap(), data);
+
+            // 空内容
+            let empty = encryptor.encrypt_with(b"", &options).unwrap();
+            assert_eq!(encryptor.decrypt(&empty).unwrap(), b"");
+        }
+
+        // 未知的压缩方式
+        let mut encrypted = encryptor.encrypt(&data).unwrap();
+        encrypted[Encryptor::MAGIC_HEADER.len() + 3] = 3;
+        assert!(encryptor.decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn test_secret_key() {
+        let key = SecretKey::generate();
+        let hex = key.to_hex();
+        assert_eq!(SecretKey::from_hex(&hex).unwrap(), key);
+        assert_ne!(SecretKey::generate(), key);
+        assert!(SecretKey::from_slice(&[1u8; 31]).is_none());
+        assert!(SecretKey::from_hex("zz").is_none());
+
+        // Debug 输出（包括包含密钥的结构体）不泄露密钥
+        let key_file = KeyFile { kdf: Kdf::Raw, key };
+        let debug = format!("{:?}", key_file);
+        assert!(debug.contains("SecretKey(..)"));
+        assert!(!debug.contains(hex.as_str()));
+        assert!(!debug.contains(&format!("{:?}", key_file.key.expose())));
+    }
+
+    #[test]
+    fn test_padding() {
+        let encryptor = Encryptor::new(b"test-key-12345").unwrap();
+        let chunk = Encryptor::STREAM_CHUNK_SIZE;
+        // 填充后的明文长度
+        let padded_len = |encrypted: &[u8]| {
+            let segment = Encryptor::NONCE_SIZE + Encryptor::TAG_SIZE;
+            let body = encrypted.len() - Header::PREFIX_LEN;
+            body - body.div_ceil(chunk + segment) * segment
+        };
+
+        assert_eq!(Padding::Padme.padded_len(1025), 1088);
+        assert_eq!(Padding::Padme.padded_len(1 << 20), 1 << 20);
+        assert_eq!(Padding::Block(512).padded_len(513), 1024);
+        assert_eq!(Padding::from_name("4096").unwrap(), Padding::Block(4096));
+        assert!(Padding::from_name("0").is_err());
+
+        // 末尾或段边界附近的 0x80 和 0 字节不能被误当作填充
+        let mut tricky = vec![7u8; chunk - 1];
+        tricky.push(Padding::MARKER);
+        tricky.extend(vec![0u8; chunk]);
+        tricky.extend(b"tail\x80\0\0");
+        let inputs: Vec<Vec<u8>> = vec![
+            Vec::new(),
+            b"\x80".to_vec(),
+            b"hello\x80\0\0".to_vec(),
+            vec![0u8; chunk + 3],
+            tricky,
+        ];
+
+        for padding in [Padding::Block(4096), Padding::Padme] {
+            let options = EncryptOptions {
+                padding,
+                ..Default::default()
+            };
+            for data in &inputs {
+                let encrypted = encryptor.encrypt_with(data, &options).unwrap();
+                let (header, _) = Header::parse(&encrypted).unwrap();
+                assert_eq!(header.flags, padding.flags());
+                assert_eq!(
+                    padded_len(&encrypted) as u64,
+                    padding.padded_len(data.len() as u64 + 1)
+                );
+                assert_eq!(&encryptor.decrypt(&encrypted).unwrap(), data);
+
+                // 每次只读一个字节
+                let mut reader = encryptor.decrypt_reader(&encrypted[..]).unwrap();
+                let mut decrypted = Vec::new();
+                let mut byte = [0u8; 1];
+                while reader.read(&mut byte).unwrap() == 1 {
+                    decrypted.push(byte[0]);
+                }
+                assert_eq!(&decrypted, data);
+            }
+        }
+
+        // 长度相近的内容填充后密文长度相同
+        let options = EncryptOptions {
+            padding: Padding::Block(4096),
+            ..Default::default()
+        };
+        let short = encryptor.encrypt_with(&[1u8; 100], &options).unwrap();
+        let long = encryptor.encrypt_with(&[1u8; 4000], &options).unwrap();
+        assert_eq!(short.len(), long.len());
+
+        // 同时设置两种填充方式的标志位无效
+        let mut encrypted = short;
+        encrypted[Encryptor::MAGIC_HEADER.len() + 3] |= Header::PADDING_MASK;
+        assert!(encryptor.decrypt(&encrypted).is_err());
+
+        // 没有填充标记的明文无法去除填充
+        let mut encrypted = encryptor.encrypt(b"no marker").unwrap();
+        encrypted[Encryptor::MAGIC_HEADER.len() + 3] |= Header::PADDING_BLOCK;
+        assert!(encryptor.decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn test_cipher_suites() {
+        let encryptor = Encryptor::new(b"test-key-12345").unwrap();
+        let data = vec![42u8; Encryptor::STREAM_CHUNK_SIZE * 2 + 10];
+        let mut outputs = Vec::new();
+
+        for suite in CipherSuite::ALL {
+            assert_eq!(CipherSuite::from_name(suite.name()).unwrap(), suite);
+            let options = EncryptOptions {
+                suite,
+                ..Default::default()
+            };
+            let encrypted = encryptor.encrypt_with(&data, &options).unwrap();
+            let (header, _) = Header::parse(&encrypted).unwrap();
+            assert_eq!(header.suite, suite);
+            assert_eq!(encryptor.decrypt(&encrypted).unwrap(), data);
+
+            // 篡改第二段
+            let mut tampered = encrypted.clone();
+            let offset = Header::PREFIX_LEN + suite.segment_size() + 20;
+            tampered[offset] ^= 1;
+            assert!(encryptor.decrypt(&tampered).is_err());
+            outputs.push(encrypted);
+        }
+
+        outputs.dedup();
+        assert_eq!(outputs.len(), CipherSuite::ALL.len());
+        assert!(CipherSuite::from_name("des").is_err());
+    }
+
+    #[test]
+    fn test_path_binding() {
+        let encryptor = Encryptor::new(b"test-key-12345").unwrap();
+        let keyring = Keyring::new(Encryptor::new(b"test-key-12345").unwrap());
+        let data = vec![42u8; Encryptor::STREAM_CHUNK_SIZE + 10];
+        let options = EncryptOptions {
+            path: Some("config/prod.env"),
+            ..Default::default()
+        };
+        let encrypted = encryptor.encrypt_with(&data, &options).unwrap();
+
+        let (header, _) = Header::parse(&encrypted).unwrap();
+        assert_eq!(header.path.as_deref(), Some("config/prod.env"));
+        assert_eq!(encryptor.decrypt(&encrypted).unwrap(), data);
+        let mut decrypted = Vec::new();
+        keyring
+            .decrypt_reader(&encrypted[..])
+            .unwrap()
+            .read_to_end(&mut decrypted)
+            .unwrap();
+        assert_eq!(decrypted, data);
+
+        // 与未绑定或绑定其他路径的密文不同
+        assert_ne!(encrypted, encryptor.encrypt(&data).unwrap());
+        let other = EncryptOptions {
+            path: Some("config/dev.env"),
+            ..Default::default()
+        };
+        assert_ne!(encrypted, encryptor.encrypt_with(&data, &other).unwrap());
+
+        // 路径参与认证，不能修改
+        let mut tampered = encrypted.clone();
+        let start = Header::PREFIX_LEN + 2 + "config/".len();
+        tampered[start..start + 4].copy_from_slice(b"dev_");
+        assert!(encryptor.decrypt(&tampered).is_err());
+    }
+
+    #[test]
+    fn test_argon2id_key_derivation() {
+        let mut params = KdfParams::generate();
+        // 测试中使用较低的开销
+        params.m_cost = 64;
+        params.t_cost = 1;
+
+        let key1 = params.derive_key(b"correct horse").unwrap();
+        let key2 = params.derive_key(b"correct horse").unwrap();
+        assert_eq!(key1, key2);
+
+        // 不同的盐派生出不同的密钥
+        let mut other = params.clone();
+        other.salt = [0u8; KdfParams::SALT_SIZE];
+        assert_ne!(other.derive_key(b"correct horse").unwrap(), key1);
+
+        // 参数可以保存后再读取
+        assert_eq!(KdfParams::parse(&params.to_toml()).unwrap(), params);
+    }
+
+    #[test]
+    fn test_supplied_key() {
+        let key_file = KeyFile::generate();
+        let export = KeyExport {
+            key_file: key_file.clone(),
+            created: "2024-01-01T00:00:00Z".to_string(),
+        };
+
+        let hex = format!("{}\n", *key_file.key.to_hex());
+        for content in [hex.as_str(), &key_file.to_toml(), &export.to_toml()] {
+            assert_eq!(
+                KeyFile::from_supplied(content.as_bytes()).unwrap(),
+                key_file
+            );
+        }
+
+        // 旧版本的密钥字符串和受保护的密钥文件都不接受
+        assert!(KeyFile::from_supplied(b"my-legacy-passphrase").is_err());
+        assert!(KeyFile::from_supplied(&[0xff, 0xfe]).is_err());
+    }
+
+    #[test]
+    fn test_protected_key_file() {
+        let mut params = KdfParams::generate();
+        // 测试中使用较低的开销
+        params.m_cost = 64;
+        params.t_cost = 1;
+
+        let key_file = KeyFile::generate();
+        let protected = ProtectedKeyFile::protect(&key_file, b"correct horse", params).unwrap();
+        let content = protected.to_toml();
+        assert!(ProtectedKeyFile::is_protected(content.as_bytes()));
+        assert!(!ProtectedKeyFile::is_protected(
+            key_file.to_toml().as_bytes()
+        ));
+        assert!(!content.contains(key_file.key.to_hex().as_str()));
+
+        // 受保护的密钥文件不能被当作旧版本的密钥字符串读取
+        assert!(KeyFile::load(content.as_bytes()).is_err());
+
+        let parsed = ProtectedKeyFile::parse(&content).unwrap();
+        assert_eq!(parsed.fingerprint, key_file.fingerprint_hex());
+        assert_eq!(parsed.unprotect(b"correct horse").unwrap(), key_file);
+        assert!(parsed.unprotect(b"wrong horse").is_err());
+
+        // 修改明文保存的指纹后无法解开
+        let tampered = content.replace(&parsed.fingerprint, &"0".repeat(16));
+        assert!(ProtectedKeyFile::parse(&tampered)
+            .unwrap()
+            .unprotect(b"correct horse")
+            .is_err());
+    }
+
+    #[test]
+    fn test_generated_key() {
+        let key1 = KeyFile::generate();
+        let key2 = KeyFile::generate();
+        assert_eq!(key1.kdf, Kdf::Raw);
+        assert_ne!(key1.key, key2.key);
+        assert_eq!(KeyFile::parse(key1.to_toml().as_bytes()), Some(key1));
+    }
+
+    #[test]
+    fn test_key_file() {
+        let key_file = KeyFile {
+            kdf: Kdf::Argon2id,
+            key: SecretKey::from_slice(&[42u8; 32]).unwrap(),
+        };
+        let content = key_file.to_toml();
+        assert_eq!(KeyFile::parse(content.as_bytes()), Some(key_file));
+
+        // 新格式的密钥文件使用其中保存的密钥，并在头部记录派生方式
+        let encryptor = Encryptor::from_key_file(content.as_bytes()).unwrap();
+        let encrypted = encryptor.encrypt(b"Hello, World!").unwrap();
+        let (header, _) = Header::parse(&encrypted).unwrap();
+        assert_eq!(header.kdf, Kdf::Argon2id);
+
+        // 旧版本的密钥文件仍按密钥字符串处理
+        assert_eq!(KeyFile::parse(b"test-key-12345"), None);
+        let legacy = Encryptor::from_key_file(b"test-key-12345").unwrap();
+        assert_eq!(
+            legacy.fingerprint(),
+            Encryptor::new(b"test-key-12345").unwrap().fingerprint()
+        );
+    }
+
+    #[test]
+    fn test_key_export() {
+        let export = KeyExport {
+            key_file: KeyFile::generate(),
+            created: "2024-01-01T00:00:00Z".to_string(),
+        };
+        let content = export.to_toml();
+        assert_eq!(KeyExport::parse(&content).unwrap(), export);
+
+        // 指纹与密钥不一致时拒绝导入
+        let tampered = content.replace(&export.key_file.fingerprint_hex(), "0000000000000000");
+        assert!(KeyExport::parse(&tampered).is_err());
+
+        // 旧版本的密钥字符串导出后与原密钥等价
+        let legacy = KeyFile::load(b"test-key-12345").unwrap();
+        assert_eq!(
+            legacy.fingerprint_hex(),
+            Encryptor::new(b"test-key-12345").unwrap().fingerprint_hex()
+        );
+    }
+
+    #[test]
+    fn test_keyring_selects_key_by_fingerprint() {
+        let old = Encryptor::new(b"test-key-12345").unwrap();
+        let encrypted = old.encrypt(b"Hello, World!").unwrap();
+
+        // 轮换后当前密钥无法解密，但密钥环中的旧密钥可以
+        let mut keyring = Keyring::new(Encryptor::new(b"test-key-67890").unwrap());
+        assert!(keyring.decrypt(&encrypted).is_err());
+        keyring.add_retired(Encryptor::new(b"test-key-12345").unwrap());
+        assert_eq!(keyring.decrypt(&encrypted).unwrap(), b"Hello, World!");
+
+        // 新内容使用当前密钥加密
+        let reencrypted = keyring.encrypt(b"Hello, World!").unwrap();
+        let (header, _) = Header::parse(&reencrypted).unwrap();
+        assert_eq!(header.fingerprint, Some(keyring.current().fingerprint()));
+    }
+
+    #[test]
+    fn test_keyring_legacy_blob() {
+        let key = b"test-key-12345";
+        let data = b"Hello, World!";
+
+        // 旧格式没有指纹，密钥环依次尝试
+        let cipher = Aes256Gcm::new_from_slice(&Sha256::digest(key)).unwrap();
+        let nonce_bytes = [3u8; Encryptor::NONCE_SIZE];
+        let mut legacy = Encryptor::MAGIC_HEADER.to_vec();
+        legacy.extend_from_slice(&nonce_bytes);
+        legacy.extend(
+            cipher
+                .encrypt(GenericArray::from_slice(&nonce_bytes), &data[..])
+                .unwrap(),
+        );
+
+        let mut keyring = Keyring::new(Encryptor::new(b"test-key-67890").unwrap());
+        keyring.add_retired(Encryptor::new(key).unwrap());
+        assert_eq!(keyring.decrypt(&legacy).unwrap(), data);
+    }
+
+    #[test]
+    fn test_legacy_blob_like_path_bound_header() {
+        let key = b"test-key-12345";
+        let data = b"Hello, World!";
+
+        // 旧格式的 nonce 恰好以绑定路径的 v3 头部开头，路径长度取自密文，超出数据末尾
+        let cipher = Aes256Gcm::new_from_slice(&Sha256::digest(key)).unwrap();
+        let mut nonce_bytes = [0u8; Encryptor::NONCE_SIZE];
+        nonce_bytes[0] = Header::V3;
+        nonce_bytes[3] = Header::PATH_BOUND;
+        let mut legacy = Encryptor::MAGIC_HEADER.to_vec();
+        legacy.extend_from_slice(&nonce_bytes);
+        legacy.extend(
+            cipher
+                .encrypt(GenericArray::from_slice(&nonce_bytes), &data[..])
+                .unwrap(),
+        );
+        let path_len =
+            u16::from_be_bytes([legacy[Header::PREFIX_LEN], legacy[Header::PREFIX_LEN + 1]]);
+        assert!(Header::PREFIX_LEN + 2 + path_len as usize > legacy.len());
+
+        let mut keyring = Keyring::new(Encryptor::new(b"test-key-67890").unwrap());
+        keyring.add_retired(Encryptor::new(key).unwrap());
+        assert_eq!(keyring.decrypt(&legacy).unwrap(), data);
+
+        let mut output = Vec::new();
+        keyring
+            .decrypt_reader(&legacy[..])
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, data);
+    }
 }
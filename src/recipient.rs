@@ -0,0 +1,244 @@
+use crate::crypto::{take_secret_field, CryptoError, KeyFile, SecretKey};
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::{Zeroize, Zeroizing};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const WRAP_KEY_LABEL: &[u8] = b"git-cryptx/wrap";
+const NONCE_SIZE: usize = 12;
+
+fn parse_toml(content: &str) -> Result<toml::Value, CryptoError> {
+    content
+        .parse()
+        .map_err(|e: toml::de::Error| CryptoError::KeyError(e.to_string()))
+}
+
+fn hex_field<const N: usize>(value: &toml::Value, name: &str) -> Result<[u8; N], CryptoError> {
+    value
+        .get(name)
+        .and_then(|v| v.as_str())
+        .and_then(|v| hex::decode(v).ok())
+        .and_then(|v| v.try_into().ok())
+        .ok_or_else(|| CryptoError::KeyError(format!("{} 格式错误", name)))
+}
+
+fn check_format(value: &toml::Value, format: &str, version: i64) -> Result<(), CryptoError> {
+    if value.get("format").and_then(|v| v.as_str()) != Some(format) {
+        return Err(CryptoError::KeyError(format!("不是 {} 文件", format)));
+    }
+    if value.get("version").and_then(|v| v.as_integer()) != Some(version) {
+        return Err(CryptoError::KeyError("不支持的文件版本".to_string()));
+    }
+    Ok(())
+}
+
+// 解析十六进制格式的 X25519 公钥
+pub fn parse_public_key(value: &str) -> Result<PublicKey, CryptoError> {
+    let bytes: [u8; 32] = hex::decode(value.trim())
+        .ok()
+        .and_then(|v| v.try_into().ok())
+        .ok_or_else(|| CryptoError::KeyError("公钥必须是 64 位十六进制字符".to_string()))?;
+    Ok(PublicKey::from(bytes))
+}
+
+// 公钥指纹，用作 .git-cryptx/keys 中的文件名
+pub fn public_key_fingerprint(public: &PublicKey) -> String {
+    hex::encode(&Sha256::digest(public.as_bytes())[..8])
+}
+
+// 成员的 X25519 身份（私钥）文件
+pub struct Identity {
+    secret: StaticSecret,
+}
+
+impl Identity {
+    const FORMAT: &'static str = "git-cryptx-identity";
+    const VERSION: i64 = 1;
+
+    pub fn generate() -> Self {
+        Self {
+            secret: StaticSecret::random_from_rng(OsRng),
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from(&self.secret)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, CryptoError> {
+        let mut value = parse_toml(content)?;
+        let secret_hex = take_secret_field(&mut value, "secret");
+        check_format(&value, Self::FORMAT, Self::VERSION)?;
+        let secret = secret_hex
+            .as_deref()
+            .and_then(|hex| SecretKey::from_hex(hex))
+            .ok_or_else(|| CryptoError::KeyError("secret 格式错误".to_string()))?;
+        Ok(Self {
+            secret: StaticSecret::from(*secret.expose()),
+        })
+    }
+
+    pub fn to_toml(&self) -> Zeroizing<String> {
+        Zeroizing::new(format!(
+            "format = \"{}\"\nversion = {}\npublic = \"{}\"\nsecret = \"{}\"\n",
+            Self::FORMAT,
+            Self::VERSION,
+            hex::encode(self.public_key().as_bytes()),
+            *Zeroizing::new(hex::encode(self.secret.as_bytes()))
+        ))
+    }
+}
+
+// 为某个成员加密保存的仓库密钥，保存在 .git-cryptx/keys/<公钥指纹>
+//
+// 使用临时 X25519 密钥与成员公钥协商出共享密钥，再用 AES-256-GCM 加密密钥文件
+pub struct WrappedKey {
+    recipient: PublicKey,
+    ephemeral: PublicKey,
+    nonce: [u8; NONCE_SIZE],
+    ciphertext: Vec<u8>,
+}
+
+impl WrappedKey {
+    const FORMAT: &'static str = "git-cryptx-wrapped-key";
+    const VERSION: i64 = 1;
+
+    pub fn wrap(key_file: &KeyFile, recipient: &PublicKey) -> Result<Self, CryptoError> {
+        let ephemeral_secret = StaticSecret::random_from_rng(OsRng);
+        let ephemeral = PublicKey::from(&ephemeral_secret);
+        let shared = ephemeral_secret.diffie_hellman(recipient);
+
+        let mut nonce = [0u8; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+
+        let cipher = Self::cipher(shared.as_bytes(), &ephemeral, recipient)?;
+        let ciphertext = cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: key_file.to_toml().as_bytes(),
+                    aad: Self::FORMAT.as_bytes(),
+                },
+            )
+            .map_err(|e| CryptoError::EncryptError(e.to_string()))?;
+
+        Ok(Self {
+            recipient: *recipient,
+            ephemeral,
+            nonce,
+            ciphertext,
+        })
+    }
+
+    pub fn recipient(&self) -> &PublicKey {
+        &self.recipient
+    }
+
+    pub fn unwrap(&self, identity: &Identity) -> Result<KeyFile, CryptoError> {
+        if identity.public_key() != self.recipient {
+            return Err(CryptoError::KeyError("身份与接收者不匹配".to_string()));
+        }
+
+        let shared = identity.secret.diffie_hellman(&self.ephemeral);
+        let cipher = Self::cipher(shared.as_bytes(), &self.ephemeral, &self.recipient)?;
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(
+                    Nonce::from_slice(&self.nonce),
+                    Payload {
+                        msg: &self.ciphertext,
+                        aad: Self::FORMAT.as_bytes(),
+                    },
+                )
+                .map_err(|e| CryptoError::EncryptError(e.to_string()))?,
+        );
+
+        KeyFile::parse(&plaintext).ok_or_else(|| CryptoError::KeyError("密钥格式错误".to_string()))
+    }
+
+    // 由共享密钥和双方公钥派生包装密钥
+    fn cipher(
+        shared: &[u8],
+        ephemeral: &PublicKey,
+        recipient: &PublicKey,
+    ) -> Result<Aes256Gcm, CryptoError> {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(shared)
+            .map_err(|e| CryptoError::KeyError(e.to_string()))?;
+        mac.update(WRAP_KEY_LABEL);
+        mac.update(ephemeral.as_bytes());
+        mac.update(recipient.as_bytes());
+        let mut key = mac.finalize().into_bytes();
+        let cipher =
+            Aes256Gcm::new_from_slice(&key).map_err(|e| CryptoError::KeyError(e.to_string()));
+        key.as_mut_slice().zeroize();
+        cipher
+    }
+
+    pub fn parse(content: &str) -> Result<Self, CryptoError> {
+        let value = parse_toml(content)?;
+        check_format(&value, Self::FORMAT, Self::VERSION)?;
+        let recipient: [u8; 32] = hex_field(&value, "recipient")?;
+        let ephemeral: [u8; 32] = hex_field(&value, "ephemeral")?;
+        let ciphertext = value
+            .get("ciphertext")
+            .and_then(|v| v.as_str())
+            .and_then(|v| hex::decode(v).ok())
+            .ok_or_else(|| CryptoError::KeyError("ciphertext 格式错误".to_string()))?;
+
+        Ok(Self {
+            recipient: PublicKey::from(recipient),
+            ephemeral: PublicKey::from(ephemeral),
+            nonce: hex_field(&value, "nonce")?,
+            ciphertext,
+        })
+    }
+
+    pub fn to_toml(&self) -> String {
+        format!(
+            "format = \"{}\"\nversion = {}\nrecipient = \"{}\"\nephemeral = \"{}\"\nnonce = \"{}\"\nciphertext = \"{}\"\n",
+            Self::FORMAT,
+            Self::VERSION,
+            hex::encode(self.recipient.as_bytes()),
+            hex::encode(self.ephemeral.as_bytes()),
+            hex::encode(self.nonce),
+            hex::encode(&self.ciphertext)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_unwrap() {
+        let identity = Identity::generate();
+        let key_file = KeyFile::generate();
+
+        let wrapped = WrappedKey::wrap(&key_file, &identity.public_key()).unwrap();
+        let wrapped = WrappedKey::parse(&wrapped.to_toml()).unwrap();
+        assert_eq!(wrapped.unwrap(&identity).unwrap(), key_file);
+
+        // 其他成员的身份无法解开
+        let other = Identity::generate();
+        assert!(wrapped.unwrap(&other).is_err());
+    }
+
+    #[test]
+    fn test_identity_file() {
+        let identity = Identity::generate();
+        let parsed = Identity::parse(&identity.to_toml()).unwrap();
+        assert_eq!(parsed.public_key(), identity.public_key());
+
+        let public = hex::encode(identity.public_key().as_bytes());
+        assert_eq!(parse_public_key(&public).unwrap(), identity.public_key());
+        assert!(parse_public_key("abcd").is_err());
+    }
+}
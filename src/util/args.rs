@@ -0,0 +1,37 @@
+// 判断参数中是否包含某个开关，例如 --raw
+pub fn has_flag(parameters: &[String], flag: &str) -> bool {
+    parameters.iter().any(|p| p == flag)
+}
+
+// 获取带值选项的值，例如 --m-cost 65536
+pub fn flag_value<'a>(parameters: &'a [String], flag: &str) -> Option<&'a str> {
+    parameters
+        .iter()
+        .position(|p| p == flag)
+        .and_then(|i| parameters.get(i + 1))
+        .map(|v| v.as_str())
+}
+
+// 获取可以重复出现的带值选项的所有值，例如 --remove-recipient a --remove-recipient b
+pub fn flag_values<'a>(parameters: &'a [String], flag: &str) -> Vec<&'a str> {
+    parameters
+        .iter()
+        .zip(parameters.iter().skip(1))
+        .filter(|(p, _)| *p == flag)
+        .map(|(_, v)| v.as_str())
+        .collect()
+}
+
+// 获取位置参数，跳过开关以及带值选项和它们的值
+pub fn positional_args<'a>(parameters: &'a [String], value_flags: &[&str]) -> Vec<&'a str> {
+    let mut result = Vec::new();
+    let mut iter = parameters.iter();
+    while let Some(p) = iter.next() {
+        if value_flags.contains(&p.as_str()) {
+            iter.next();
+        } else if !p.starts_with("--") {
+            result.push(p.as_str());
+        }
+    }
+    result
+}
@@ -1,6 +1,11 @@
+use std::collections::HashMap;
 use std::fs;
+use std::io::{self, BufRead, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use zeroize::Zeroizing;
 
 pub fn find_git_root() -> Option<PathBuf> {
     let output = Command::new("git")
@@ -21,43 +26,432 @@ pub fn ensure_git_cryptx_dir(git_root: &Path) -> Result<PathBuf, String> {
     let encrypt_dir = git_dir.join("cryptx");
     let keys_dir = encrypt_dir.join("keys");
 
-    fs::create_dir_all(&keys_dir).map_err(|e| format!("无法创建加密目录: {}", e))?;
+    create_private_dir(&keys_dir).map_err(|e| format!("无法创建加密目录: {}", e))?;
+
+    // create_private_dir 不修改已经存在的目录，旧版本按 umask 创建的目录（通常为 0755）在这里收紧
+    #[cfg(unix)]
+    for dir in [&encrypt_dir, &keys_dir, &encrypt_dir.join("sessions")] {
+        if dir.is_dir() {
+            fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+                .map_err(|e| format!("无法设置加密目录权限: {}", e))?;
+        }
+    }
 
     Ok(encrypt_dir)
 }
 
+// 创建只有所有者可以访问的目录 (0700)，包括所有新建的上级目录
+pub fn create_private_dir(path: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    builder.mode(0o700);
+    builder.create(path)
+}
+
+// 以 0600 权限写入密钥文件：先写入同一目录下的临时文件再重命名，
+// 中途失败不会留下只写了一半的密钥，也不会有短暂可被其他用户读取的时间窗口
+pub fn write_key_file(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    #[cfg(unix)]
+    options.mode(0o600);
+    write_via_temp_file(path, content, &mut options, None)
+}
+
+// 保留原有权限替换文件的内容，同样先写入临时文件再重命名，中途失败时原文件不变
+pub fn replace_file(path: &Path, content: &[u8]) -> io::Result<()> {
+    let permissions = fs::metadata(path)?.permissions();
+    write_via_temp_file(
+        path,
+        content,
+        &mut fs::OpenOptions::new(),
+        Some(permissions),
+    )
+}
+
+fn write_via_temp_file(
+    path: &Path,
+    content: &[u8],
+    options: &mut fs::OpenOptions,
+    permissions: Option<fs::Permissions>,
+) -> io::Result<()> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()));
+
+    let result = options
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(content)?;
+            if let Some(permissions) = permissions {
+                file.set_permissions(permissions)?;
+            }
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+// 列出组或其他用户可以访问的密钥目录、会话目录及其中的文件，返回路径和权限位
+#[cfg(unix)]
+pub fn list_insecure_key_paths(git_root: &Path) -> Vec<(PathBuf, u32)> {
+    let mut insecure = Vec::new();
+    for dir in [".git/cryptx/keys", ".git/cryptx/sessions"] {
+        let dir = git_root.join(dir);
+        let mut paths = vec![dir.clone()];
+        if let Ok(entries) = fs::read_dir(&dir) {
+            paths.extend(entries.filter_map(|e| e.ok()).map(|e| e.path()));
+        }
+        for path in paths {
+            if let Ok(metadata) = fs::metadata(&path) {
+                let mode = metadata.permissions().mode() & 0o777;
+                if mode & 0o077 != 0 {
+                    insecure.push((path, mode));
+                }
+            }
+        }
+    }
+    insecure.sort();
+    insecure
+}
+
+#[cfg(not(unix))]
+pub fn list_insecure_key_paths(_git_root: &Path) -> Vec<(PathBuf, u32)> {
+    Vec::new()
+}
+
+// 默认密钥对应的过滤器名称
+pub const FILTER_NAME: &str = "git-cryptx";
+// 默认密钥的文件名
+const DEFAULT_KEY_FILE: &str = "global_ase_key";
+
+// 命名密钥只允许字母、数字、- 和 _，用作文件名和过滤器名称的一部分
+pub fn is_valid_key_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != DEFAULT_KEY_FILE
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// 密钥对应的过滤器名称：默认密钥为 git-cryptx，命名密钥为 git-cryptx-<名称>
+pub fn filter_name(key_name: Option<&str>) -> String {
+    match key_name {
+        Some(name) => format!("{}-{}", FILTER_NAME, name),
+        None => FILTER_NAME.to_string(),
+    }
+}
+
+// 提交到仓库中的 .git-cryptx 目录，保存各成员共用的配置
+pub fn ensure_cryptx_config_dir(git_root: &Path) -> Result<PathBuf, String> {
+    let dir = git_root.join(".git-cryptx");
+    fs::create_dir_all(&dir).map_err(|e| format!("无法创建配置目录: {}", e))?;
+    Ok(dir)
+}
+
+// 为各成员包装的仓库密钥，保存在提交到仓库中的目录
+pub fn get_recipients_dir(git_root: &Path, key_name: Option<&str>) -> PathBuf {
+    let dir = git_root.join(".git-cryptx/keys");
+    match key_name {
+        Some(name) => dir.join(name),
+        None => dir,
+    }
+}
+
+pub fn ensure_recipients_dir(git_root: &Path, key_name: Option<&str>) -> Result<PathBuf, String> {
+    let recipients_dir = get_recipients_dir(git_root, key_name);
+    fs::create_dir_all(&recipients_dir).map_err(|e| format!("无法创建接收者目录: {}", e))?;
+    Ok(recipients_dir)
+}
+
 pub fn get_key_path(git_root: &Path) -> PathBuf {
-    git_root.join(".git/cryptx/keys/global_ase_key")
+    get_named_key_path(git_root, None)
+}
+
+pub fn get_named_key_path(git_root: &Path, key_name: Option<&str>) -> PathBuf {
+    git_root
+        .join(".git/cryptx/keys")
+        .join(key_name.unwrap_or(DEFAULT_KEY_FILE))
+}
+
+// unlock 为受口令保护的密钥打开的会话，保存解开后的密钥和过期时间
+pub fn get_session_path(git_root: &Path, key_name: Option<&str>) -> PathBuf {
+    get_sessions_dir(git_root).join(key_name.unwrap_or(DEFAULT_KEY_FILE))
+}
+
+pub fn get_sessions_dir(git_root: &Path) -> PathBuf {
+    git_root.join(".git/cryptx/sessions")
+}
+
+// 读取密钥文件，返回的内容在丢弃时清零
+pub fn read_key_file(path: &Path) -> std::io::Result<Zeroizing<Vec<u8>>> {
+    fs::read(path).map(Zeroizing::new)
+}
+
+// 轮换后保留的旧密钥：<密钥文件名>.<指纹>，只用于解密历史提交
+pub fn get_retired_key_path(git_root: &Path, key_name: Option<&str>, fingerprint: &str) -> PathBuf {
+    let key_path = get_named_key_path(git_root, key_name);
+    let file_name = format!(
+        "{}.{}",
+        key_path.file_name().unwrap_or_default().to_string_lossy(),
+        fingerprint
+    );
+    key_path.with_file_name(file_name)
+}
+
+// 列出轮换后保留的旧密钥
+pub fn list_retired_key_paths(git_root: &Path, key_name: Option<&str>) -> Vec<PathBuf> {
+    let key_path = get_named_key_path(git_root, key_name);
+    let prefix = format!(
+        "{}.",
+        key_path.file_name().unwrap_or_default().to_string_lossy()
+    );
+    let entries = match key_path.parent().map(fs::read_dir) {
+        Some(Ok(entries)) => entries,
+        _ => return Vec::new(),
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .map(|n| n.to_string_lossy().starts_with(&prefix))
+                .unwrap_or(false)
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+// 是否配置了任意密钥（默认密钥或命名密钥）
+pub fn has_any_key(git_root: &Path) -> bool {
+    fs::read_dir(git_root.join(".git/cryptx/keys"))
+        .map(|mut entries| entries.any(|e| e.map(|e| e.path().is_file()).unwrap_or(false)))
+        .unwrap_or(false)
+}
+
+// Argon2id 参数，提交到仓库中，各成员共用同一个盐
+pub fn get_kdf_path(git_root: &Path) -> PathBuf {
+    git_root.join(".git-cryptx/kdf.toml")
+}
+
+// 旧版本保存在本地的 Argon2id 参数，只在仓库中没有参数时读取
+pub fn get_local_kdf_path(git_root: &Path) -> PathBuf {
+    git_root.join(".git/cryptx/kdf.toml")
 }
 
-// 新增：配置 Git 过滤器
-pub fn configure_git_filter(git_root: &Path) -> Result<(), String> {
+// 加密套件、压缩、填充和路径绑定设置，提交到仓库中，各克隆产生相同的密文
+pub fn get_config_path(git_root: &Path) -> PathBuf {
+    git_root.join(".git-cryptx/config.toml")
+}
+
+// 新增：配置 Git 过滤器，命名密钥使用 git-cryptx-<名称> 过滤器
+pub fn configure_git_filter(git_root: &Path, key_name: Option<&str>) -> Result<(), String> {
+    let filter = filter_name(key_name);
+    let option = match key_name {
+        Some(name) => format!(" --name {}", name),
+        None => String::new(),
+    };
     let configs = [
-        ("filter.git-cryptx.clean", "git-cryptx clean %f"),
-        ("filter.git-cryptx.smudge", "git-cryptx smudge %f"),
-        ("filter.git-cryptx.required", "true"),
-        ("diff.git-cryptx.textconv", "git-cryptx diff"),
+        (
+            format!("filter.{}.clean", filter),
+            format!("git-cryptx clean{} %f", option),
+        ),
+        (
+            format!("filter.{}.smudge", filter),
+            format!("git-cryptx smudge{} %f", option),
+        ),
+        // 支持长驻过滤进程的 Git 会优先使用 process，避免每个文件启动一次
+        (
+            format!("filter.{}.process", filter),
+            format!("git-cryptx filter-process{}", option),
+        ),
+        (format!("filter.{}.required", filter), "true".to_string()),
+        (
+            format!("diff.{}.textconv", filter),
+            format!("git-cryptx diff{}", option),
+        ),
     ];
 
     for (key, value) in configs.iter() {
-        let output = Command::new("git")
-            .args(["config", key, value])
-            .current_dir(git_root)
-            .output()
-            .map_err(|e| format!("无法执行 git config: {}", e))?;
-
-        if !output.status.success() {
-            return Err(format!(
-                "无法配置 Git {}: {}",
-                key,
-                String::from_utf8_lossy(&output.stderr)
-            ));
-        }
+        set_git_config(git_root, key, value)?;
+    }
+
+    Ok(())
+}
+
+// 一次读取名称匹配正则的所有 Git 配置，避免每个配置项启动一个 git 进程；
+// Git 配置的节名和键名不区分大小写，返回的键名为小写
+pub fn get_git_configs(pattern: &str) -> HashMap<String, String> {
+    match Command::new("git")
+        .args(["config", "-z", "--get-regexp", pattern])
+        .output()
+    {
+        Ok(output) if output.status.success() => parse_git_configs(&output.stdout),
+        _ => HashMap::new(),
+    }
+}
+
+// 输出格式：<键>\n<值>\0，没有值的布尔配置只有 <键>\0；同一个键出现多次时以最后一个为准
+fn parse_git_configs(output: &[u8]) -> HashMap<String, String> {
+    String::from_utf8_lossy(output)
+        .split('\0')
+        .filter(|record| !record.is_empty())
+        .map(|record| match record.split_once('\n') {
+            Some((key, value)) => (key.to_lowercase(), value.to_string()),
+            None => (record.to_lowercase(), "true".to_string()),
+        })
+        .collect()
+}
+
+pub fn set_git_config(git_root: &Path, key: &str, value: &str) -> Result<(), String> {
+    let output = Command::new("git")
+        .args(["config", key, value])
+        .current_dir(git_root)
+        .output()
+        .map_err(|e| format!("无法执行 git config: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "无法配置 Git {}: {}",
+            key,
+            String::from_utf8_lossy(&output.stderr)
+        ));
     }
 
     Ok(())
 }
 
+// 读取文件的 Git 属性（git check-attr），按参数顺序返回，未指定的属性为 None；
+// 没有值的属性为 "set"，取消的属性（-attr）为 "unset"
+pub fn get_git_attributes(git_root: &Path, path: &str, attributes: &[&str]) -> Vec<Option<String>> {
+    let mut values = vec![None; attributes.len()];
+    let output = match Command::new("git")
+        .args(["check-attr", "-z"])
+        .args(attributes)
+        .args(["--", path])
+        .current_dir(git_root)
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        _ => return values,
+    };
+
+    // 输出格式：<路径> NUL <属性> NUL <值> NUL，每个属性一组
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let fields: Vec<&str> = stdout.split('\0').collect();
+    for record in fields.chunks_exact(3) {
+        if let Some(index) = attributes.iter().position(|a| *a == record[1]) {
+            if record[2] != "unspecified" {
+                values[index] = Some(record[2].to_string());
+            }
+        }
+    }
+    values
+}
+
+// 读取多个文件的 Git 属性，所有文件共用一个 git check-attr --stdin 进程，
+// 避免 filter-process、rotate-key 等为每个文件启动一个 git 进程。
+// 进程无法启动或出错时退回到 get_git_attributes
+pub struct AttributeReader {
+    git_root: PathBuf,
+    attributes: Vec<&'static str>,
+    process: Option<CheckAttrProcess>,
+    failed: bool,
+}
+
+struct CheckAttrProcess {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: io::BufReader<ChildStdout>,
+}
+
+impl AttributeReader {
+    pub fn new(git_root: &Path, attributes: &[&'static str]) -> Self {
+        Self {
+            git_root: git_root.to_path_buf(),
+            attributes: attributes.to_vec(),
+            process: None,
+            failed: false,
+        }
+    }
+
+    // 按参数顺序返回文件的属性，含义与 get_git_attributes 相同
+    pub fn read(&mut self, path: &str) -> Vec<Option<String>> {
+        if !self.failed {
+            match self.read_from_process(path) {
+                Ok(values) => return values,
+                Err(_) => {
+                    self.failed = true;
+                    self.process = None;
+                }
+            }
+        }
+        get_git_attributes(&self.git_root, path, &self.attributes)
+    }
+
+    fn read_from_process(&mut self, path: &str) -> io::Result<Vec<Option<String>>> {
+        if self.process.is_none() {
+            // 输出到管道时 check-attr 每处理完一个路径就刷新输出
+            let mut child = Command::new("git")
+                .args(["check-attr", "--stdin", "-z"])
+                .args(&self.attributes)
+                .current_dir(&self.git_root)
+                .env("GIT_FLUSH", "1")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()?;
+            let stdin = child.stdin.take();
+            let stdout = io::BufReader::new(child.stdout.take().expect("stdout 已设置为管道"));
+            self.process = Some(CheckAttrProcess {
+                child,
+                stdin,
+                stdout,
+            });
+        }
+        let process = self.process.as_mut().expect("进程已启动");
+
+        let stdin = process.stdin.as_mut().ok_or(io::ErrorKind::BrokenPipe)?;
+        stdin.write_all(path.as_bytes())?;
+        stdin.write_all(b"\0")?;
+        stdin.flush()?;
+
+        // 每个属性输出一组：<路径> NUL <属性> NUL <值> NUL
+        let mut values = vec![None; self.attributes.len()];
+        for _ in 0..self.attributes.len() {
+            let mut record = Vec::with_capacity(3);
+            for _ in 0..3 {
+                let mut field = Vec::new();
+                process.stdout.read_until(0, &mut field)?;
+                if field.pop() != Some(0) {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                record.push(String::from_utf8_lossy(&field).to_string());
+            }
+            if let Some(index) = self.attributes.iter().position(|a| *a == record[1]) {
+                if record[2] != "unspecified" {
+                    values[index] = Some(record[2].clone());
+                }
+            }
+        }
+        Ok(values)
+    }
+}
+
+impl Drop for CheckAttrProcess {
+    fn drop(&mut self) {
+        // 关闭输入后 git check-attr 自行退出
+        drop(self.stdin.take());
+        let _ = self.child.wait();
+    }
+}
+
 // 新增：检查 Git 过滤器配置
 pub fn check_git_filter(git_root: &Path) -> bool {
     let configs = [
@@ -108,3 +502,394 @@ pub fn reset_file(path: &Path) -> Result<(), String> {
 
     Ok(())
 }
+
+// 列出 HEAD 中使用指定过滤器的文件
+pub fn list_filtered_files(git_root: &Path, filter: &str) -> Result<Vec<String>, String> {
+    let head = Command::new("git")
+        .args(["rev-parse", "--verify", "--quiet", "HEAD"])
+        .current_dir(git_root)
+        .output()
+        .map_err(|e| format!("无法执行 git rev-parse: {}", e))?;
+    if !head.status.success() {
+        // 还没有任何提交
+        return Ok(Vec::new());
+    }
+
+    let output = Command::new("git")
+        .args(["ls-tree", "-r", "-z", "--name-only", "HEAD"])
+        .current_dir(git_root)
+        .output()
+        .map_err(|e| format!("无法执行 git ls-tree: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "无法列出文件: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    filter_by_attribute(git_root, output.stdout, filter)
+}
+
+// 暂存区中的文件条目
+pub struct IndexEntry {
+    pub mode: String,
+    pub object: String,
+    pub path: String,
+}
+
+// 列出暂存区中使用指定过滤器的文件
+pub fn list_staged_filtered_files(
+    git_root: &Path,
+    filter: &str,
+) -> Result<Vec<IndexEntry>, String> {
+    let output = Command::new("git")
+        .args(["ls-files", "-s", "-z"])
+        .current_dir(git_root)
+        .output()
+        .map_err(|e| format!("无法执行 git ls-files: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "无法列出文件: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    // 输出格式：<模式> <对象> <阶段>\t<路径>\0
+    let mut entries = Vec::new();
+    for record in output.stdout.split(|b| *b == 0).filter(|r| !r.is_empty()) {
+        let record = String::from_utf8_lossy(record);
+        let (info, path) = match record.split_once('\t') {
+            Some(parts) => parts,
+            None => continue,
+        };
+        let fields: Vec<&str> = info.split(' ').collect();
+        if fields.len() != 3 {
+            continue;
+        }
+        entries.push(IndexEntry {
+            mode: fields[0].to_string(),
+            object: fields[1].to_string(),
+            path: path.to_string(),
+        });
+    }
+
+    let mut names = Vec::new();
+    for entry in &entries {
+        names.extend_from_slice(entry.path.as_bytes());
+        names.push(0);
+    }
+    let filtered = filter_by_attribute(git_root, names, filter)?;
+    Ok(entries
+        .into_iter()
+        .filter(|e| filtered.contains(&e.path))
+        .collect())
+}
+
+// 从以 \0 分隔的路径中筛选出 filter 属性等于指定值的路径
+fn filter_by_attribute(
+    git_root: &Path,
+    names: Vec<u8>,
+    filter: &str,
+) -> Result<Vec<String>, String> {
+    let output = run_with_stdin(git_root, &["check-attr", "-z", "--stdin", "filter"], names)?;
+    if !output.status.success() {
+        return Err(format!(
+            "无法读取文件属性: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    // 输出格式：<路径>\0<属性>\0<值>\0
+    let fields: Vec<&[u8]> = output.stdout.split(|b| *b == 0).collect();
+    Ok(fields
+        .chunks(3)
+        .filter(|c| c.len() == 3 && c[2] == filter.as_bytes())
+        .map(|c| String::from_utf8_lossy(c[0]).to_string())
+        .collect())
+}
+
+fn run_with_stdin(
+    git_root: &Path,
+    args: &[&str],
+    input: Vec<u8>,
+) -> Result<std::process::Output, String> {
+    let mut child = Command::new("git")
+        .args(args)
+        .current_dir(git_root)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("无法执行 git {}: {}", args[0], e))?;
+
+    // 在单独的线程中写入 stdin，避免输出缓冲区写满时死锁
+    let mut stdin = child.stdin.take().expect("stdin 已设置为管道");
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("无法执行 git {}: {}", args[0], e))?;
+    let _ = writer.join();
+    Ok(output)
+}
+
+// 列出指定文件中有未提交修改（工作区或暂存区与 HEAD 不同）的文件
+pub fn list_modified_files(git_root: &Path, paths: &[String]) -> Result<Vec<String>, String> {
+    let output = Command::new("git")
+        .args([
+            "status",
+            "--porcelain",
+            "-z",
+            "--untracked-files=no",
+            "--no-renames",
+        ])
+        .current_dir(git_root)
+        .output()
+        .map_err(|e| format!("无法执行 git status: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "无法读取文件状态: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    // 输出格式：<XY> <路径>\0，路径相对于仓库根目录
+    Ok(output
+        .stdout
+        .split(|b| *b == 0)
+        .filter(|r| r.len() > 3)
+        .map(|r| String::from_utf8_lossy(&r[3..]).to_string())
+        .filter(|path| paths.contains(path))
+        .collect())
+}
+
+// 重新计算文件内容并更新暂存区中的文件状态。文件大小与暂存区记录不同时
+// git 不比较内容直接认为已修改，update-index --refresh 无法更新这类文件
+pub fn refresh_index_entries(git_root: &Path, paths: &[String]) -> Result<(), String> {
+    let mut names = Vec::new();
+    for path in paths {
+        names.extend_from_slice(path.as_bytes());
+        names.push(0);
+    }
+    let output = run_with_stdin(git_root, &["update-index", "-z", "--stdin"], names)?;
+    if !output.status.success() {
+        return Err(format!(
+            "无法更新暂存区: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    Ok(())
+}
+
+// 从暂存区检出指定文件，经过 smudge 过滤器
+pub fn checkout_index_entries(git_root: &Path, paths: &[String]) -> Result<(), String> {
+    let mut names = Vec::new();
+    for path in paths {
+        names.extend_from_slice(path.as_bytes());
+        names.push(0);
+    }
+    let output = run_with_stdin(
+        git_root,
+        &["checkout-index", "-f", "-u", "-z", "--stdin"],
+        names,
+    )?;
+    if !output.status.success() {
+        return Err(format!(
+            "无法检出文件: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    Ok(())
+}
+
+// 读取 HEAD 中某个文件的原始内容（即加密后的 blob）
+pub fn read_head_blob(git_root: &Path, path: &str) -> Result<Vec<u8>, String> {
+    read_blob(git_root, &format!("HEAD:{}", path))
+}
+
+// 读取对象的原始内容，不经过过滤器
+pub fn read_blob(git_root: &Path, object: &str) -> Result<Vec<u8>, String> {
+    let output = Command::new("git")
+        .args(["cat-file", "blob", object])
+        .current_dir(git_root)
+        .output()
+        .map_err(|e| format!("无法执行 git cat-file: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "无法读取文件: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    Ok(output.stdout)
+}
+
+pub fn stage_file(git_root: &Path, path: &Path) -> Result<(), String> {
+    let output = Command::new("git")
+        .args(["add", "--"])
+        .arg(path)
+        .current_dir(git_root)
+        .output()
+        .map_err(|e| format!("无法执行 git add: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "无法暂存文件: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    Ok(())
+}
+
+// 将内容原样写入对象库（不经过过滤器），返回对象 ID
+pub fn write_blob(git_root: &Path, content: Vec<u8>) -> Result<String, String> {
+    let output = run_with_stdin(
+        git_root,
+        &["hash-object", "-w", "--stdin", "--no-filters"],
+        content,
+    )?;
+    if !output.status.success() {
+        return Err(format!(
+            "无法写入对象: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// 将暂存区中的条目指向新的对象
+pub fn update_index_entry(git_root: &Path, entry: &IndexEntry) -> Result<(), String> {
+    let output = Command::new("git")
+        .args([
+            "update-index",
+            "--cacheinfo",
+            &format!("{},{},{}", entry.mode, entry.object, entry.path),
+        ])
+        .current_dir(git_root)
+        .output()
+        .map_err(|e| format!("无法执行 git update-index: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "无法更新暂存区: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_git_configs() {
+        let configs =
+            parse_git_configs(b"cryptx.missingkey\npassthrough\0cryptx.smudge\nlenient\0cryptx.smudge\nstrict\0cryptx.flag\0");
+        assert_eq!(configs["cryptx.missingkey"], "passthrough");
+        assert_eq!(configs["cryptx.smudge"], "strict");
+        assert_eq!(configs["cryptx.flag"], "true");
+        assert!(parse_git_configs(b"").is_empty());
+    }
+
+    #[test]
+    fn test_attribute_reader() {
+        let git_root = std::env::temp_dir().join(format!("git-cryptx-attr-{}", std::process::id()));
+        fs::create_dir_all(&git_root).unwrap();
+        Command::new("git")
+            .args(["init", "-q"])
+            .current_dir(&git_root)
+            .output()
+            .unwrap();
+        fs::write(
+            git_root.join(".gitattributes"),
+            "*.json cryptx-compress=zstd\n*.key -cryptx-compress cryptx-padding\n",
+        )
+        .unwrap();
+
+        let attributes = ["cryptx-compress", "cryptx-padding"];
+        let mut reader = AttributeReader::new(&git_root, &attributes);
+        for path in ["a.json", "dir/b.key", "c.txt", "a.json"] {
+            assert_eq!(
+                reader.read(path),
+                get_git_attributes(&git_root, path, &attributes)
+            );
+        }
+        assert_eq!(
+            reader.read("dir/b.key"),
+            vec![Some("unset".to_string()), Some("set".to_string())]
+        );
+        assert!(!reader.failed);
+
+        drop(reader);
+        fs::remove_dir_all(&git_root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_key_file_permissions() {
+        let git_root = std::env::temp_dir().join(format!("git-cryptx-perm-{}", std::process::id()));
+        ensure_git_cryptx_dir(&git_root).unwrap();
+        let keys_dir = git_root.join(".git/cryptx/keys");
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&keys_dir), 0o700);
+        assert_eq!(mode(&git_root.join(".git/cryptx")), 0o700);
+
+        // 覆盖已有文件，不留下临时文件
+        let key_path = get_key_path(&git_root);
+        write_key_file(&key_path, b"old").unwrap();
+        write_key_file(&key_path, b"new").unwrap();
+        assert_eq!(fs::read(&key_path).unwrap(), b"new");
+        assert_eq!(mode(&key_path), 0o600);
+        assert_eq!(fs::read_dir(&keys_dir).unwrap().count(), 1);
+        assert!(list_insecure_key_paths(&git_root).is_empty());
+
+        fs::set_permissions(&key_path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(
+            list_insecure_key_paths(&git_root),
+            vec![(key_path.clone(), 0o644)]
+        );
+
+        // 替换文件内容时保留原有权限
+        replace_file(&key_path, b"replaced").unwrap();
+        assert_eq!(fs::read(&key_path).unwrap(), b"replaced");
+        assert_eq!(mode(&key_path), 0o644);
+        assert_eq!(fs::read_dir(&keys_dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(&git_root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_existing_key_dirs_tightened() {
+        let git_root =
+            std::env::temp_dir().join(format!("git-cryptx-old-perm-{}", std::process::id()));
+        let encrypt_dir = git_root.join(".git/cryptx");
+        let dirs = [
+            encrypt_dir.clone(),
+            encrypt_dir.join("keys"),
+            encrypt_dir.join("sessions"),
+        ];
+        // 旧版本按 umask 创建的目录
+        for dir in &dirs {
+            fs::create_dir_all(dir).unwrap();
+            fs::set_permissions(dir, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        ensure_git_cryptx_dir(&git_root).unwrap();
+        for dir in &dirs {
+            assert_eq!(
+                fs::metadata(dir).unwrap().permissions().mode() & 0o777,
+                0o700
+            );
+        }
+        assert!(list_insecure_key_paths(&git_root).is_empty());
+
+        fs::remove_dir_all(&git_root).unwrap();
+    }
+}
@@ -2,3 +2,13 @@ pub fn log_error(msg: &str) {
     println!("Error: {}, learn https://git-cryptx.limitfunc.cn", msg);
     std::process::exit(1);
 }
+
+// 过滤器（clean/smudge/diff）的标准输出会被 Git 当作文件内容，错误只能写到标准错误
+pub fn log_filter_error(msg: &str) {
+    eprintln!("git-cryptx: {}", msg);
+    std::process::exit(1);
+}
+
+pub fn log_filter_warning(msg: &str) {
+    eprintln!("git-cryptx: {}", msg);
+}
@@ -1,7 +1,15 @@
+mod args;
 mod git;
 mod logger;
 mod i18n;
+mod pkt_line;
+mod prompt;
+mod time;
 
+pub use args::*;
 pub use git::*;
 pub use logger::*;
 pub use i18n::*;
+pub use pkt_line::*;
+pub use prompt::*;
+pub use time::*;
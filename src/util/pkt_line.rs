@@ -0,0 +1,250 @@
+use std::io::{self, Read, Write};
+
+// Git pkt-line 格式：4 位十六进制长度（包含长度字段本身）加数据，"0000" 为 flush 包
+pub const MAX_PKT_DATA_LEN: usize = 65516;
+
+pub enum Packet {
+    Data(Vec<u8>),
+    Flush,
+}
+
+// 读取一个数据包，输入在包边界处结束时返回 None
+pub fn read_packet<R: Read>(reader: &mut R) -> io::Result<Option<Packet>> {
+    let mut len_hex = [0u8; 4];
+    let mut filled = 0;
+    while filled < len_hex.len() {
+        match reader.read(&mut len_hex[filled..])? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => filled += n,
+        }
+    }
+
+    let len = std::str::from_utf8(&len_hex)
+        .ok()
+        .and_then(|s| usize::from_str_radix(s, 16).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "无效的 pkt-line 长度"))?;
+    match len {
+        0 => Ok(Some(Packet::Flush)),
+        1..=4 => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "不支持的 pkt-line 特殊包",
+        )),
+        _ => {
+            let mut data = vec![0u8; len - 4];
+            reader.read_exact(&mut data)?;
+            Ok(Some(Packet::Data(data)))
+        }
+    }
+}
+
+// 读取以 flush 包结束的文本行列表，去掉行尾换行符
+pub fn read_text_list<R: Read>(reader: &mut R) -> io::Result<Option<Vec<String>>> {
+    let mut lines = Vec::new();
+    loop {
+        match read_packet(reader)? {
+            Some(Packet::Data(data)) => {
+                let line = String::from_utf8(data)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                lines.push(line.trim_end_matches('\n').to_string());
+            }
+            Some(Packet::Flush) => return Ok(Some(lines)),
+            None if lines.is_empty() => return Ok(None),
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+}
+
+// 读取以 flush 包结束的二进制内容
+pub fn read_content<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    PacketReader::new(reader).read_to_end(&mut content)?;
+    Ok(content)
+}
+
+// 把以 flush 包结束的内容作为 Read，读到 flush 包时结束，内存中只保存一个包
+pub struct PacketReader<'a, R> {
+    reader: &'a mut R,
+    packet: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<'a, R: Read> PacketReader<'a, R> {
+    pub fn new(reader: &'a mut R) -> Self {
+        Self {
+            reader,
+            packet: Vec::new(),
+            pos: 0,
+            done: false,
+        }
+    }
+
+    // 跳过剩余的内容直到 flush 包，使下一个请求从包边界开始
+    pub fn drain(&mut self) -> io::Result<()> {
+        io::copy(self, &mut io::sink()).map(|_| ())
+    }
+}
+
+impl<R: Read> Read for PacketReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.packet.len() {
+            if self.done {
+                return Ok(0);
+            }
+            match read_packet(self.reader)? {
+                Some(Packet::Data(data)) => {
+                    self.packet = data;
+                    self.pos = 0;
+                }
+                Some(Packet::Flush) => self.done = true,
+                None => return Err(io::ErrorKind::UnexpectedEof.into()),
+            }
+        }
+
+        let len = buf.len().min(self.packet.len() - self.pos);
+        buf[..len].copy_from_slice(&self.packet[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+// 把写入的内容按最大包长度分成数据包，finish 写出剩余的数据和 flush 包
+pub struct PacketWriter<W: Write> {
+    writer: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> PacketWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    // 丢弃尚未写出的数据，取回内部的 writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_buffer()?;
+        write_flush(&mut self.writer)?;
+        Ok(self.writer)
+    }
+
+    fn write_buffer(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            write_packet(&mut self.writer, &self.buffer)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for PacketWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(MAX_PKT_DATA_LEN - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == MAX_PKT_DATA_LEN {
+            self.write_buffer()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_buffer()?;
+        self.writer.flush()
+    }
+}
+
+pub fn write_packet<W: Write>(writer: &mut W, data: &[u8]) -> io::Result<()> {
+    write!(writer, "{:04x}", data.len() + 4)?;
+    writer.write_all(data)
+}
+
+pub fn write_flush<W: Write>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(b"0000")
+}
+
+pub fn write_text<W: Write>(writer: &mut W, line: &str) -> io::Result<()> {
+    write_packet(writer, format!("{}\n", line).as_bytes())
+}
+
+// 按最大包长度分块写出内容，并以 flush 包结束
+pub fn write_content<W: Write>(writer: &mut W, content: &[u8]) -> io::Result<()> {
+    for chunk in content.chunks(MAX_PKT_DATA_LEN) {
+        write_packet(writer, chunk)?;
+    }
+    write_flush(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_list_roundtrip() {
+        let mut buffer = Vec::new();
+        write_text(&mut buffer, "git-filter-client").unwrap();
+        write_text(&mut buffer, "version=2").unwrap();
+        write_flush(&mut buffer).unwrap();
+        assert_eq!(&buffer[..4], b"0016");
+
+        let mut reader = &buffer[..];
+        let lines = read_text_list(&mut reader).unwrap().unwrap();
+        assert_eq!(lines, vec!["git-filter-client", "version=2"]);
+        // 输入结束
+        assert!(read_text_list(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_content_chunking() {
+        let content = vec![7u8; MAX_PKT_DATA_LEN * 2 + 10];
+        let mut buffer = Vec::new();
+        write_content(&mut buffer, &content).unwrap();
+        assert_eq!(&buffer[..4], b"fff0");
+
+        let mut reader = &buffer[..];
+        assert_eq!(read_content(&mut reader).unwrap(), content);
+
+        // 空内容只有 flush 包
+        let mut buffer = Vec::new();
+        write_content(&mut buffer, b"").unwrap();
+        assert_eq!(buffer, b"0000");
+
+        // 内容中途结束
+        let mut reader = &b"0008ab"[..];
+        assert!(read_content(&mut reader).is_err());
+    }
+
+    #[test]
+    fn test_packet_writer_reader() {
+        let content: Vec<u8> = (0..MAX_PKT_DATA_LEN * 2 + 10).map(|i| i as u8).collect();
+        let mut packets = PacketWriter::new(Vec::new());
+        // 分多次少量写入，仍按最大包长度分包
+        for chunk in content.chunks(1000) {
+            packets.write_all(chunk).unwrap();
+        }
+        let mut buffer = packets.finish().unwrap();
+        let mut expected = Vec::new();
+        write_content(&mut expected, &content).unwrap();
+        assert_eq!(buffer, expected);
+
+        // 读到 flush 包时结束，之后的数据属于下一个请求
+        write_text(&mut buffer, "next").unwrap();
+        write_flush(&mut buffer).unwrap();
+        let mut reader = &buffer[..];
+        let mut packets = PacketReader::new(&mut reader);
+        let mut head = [0u8; 10];
+        packets.read_exact(&mut head).unwrap();
+        assert_eq!(head[..], content[..10]);
+        packets.drain().unwrap();
+        assert_eq!(read_text_list(&mut reader).unwrap().unwrap(), vec!["next"]);
+    }
+}
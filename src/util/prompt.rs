@@ -0,0 +1,7 @@
+use std::io;
+use zeroize::Zeroizing;
+
+// 从终端读取口令，不回显输入
+pub fn read_passphrase(prompt: &str) -> io::Result<Zeroizing<String>> {
+    rpassword::prompt_password(format!("{}: ", prompt)).map(Zeroizing::new)
+}
@@ -0,0 +1,108 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+// 在临时仓库中运行命令，过滤器通过 PATH 找到刚构建的 git-cryptx
+fn run(dir: &Path, program: &str, args: &[&str], stdin: &[u8]) -> Output {
+    let bin_dir = Path::new(env!("CARGO_BIN_EXE_git-cryptx"))
+        .parent()
+        .unwrap();
+    let path = env::join_paths(
+        std::iter::once(bin_dir.to_path_buf())
+            .chain(env::split_paths(&env::var_os("PATH").unwrap())),
+    )
+    .unwrap();
+    let mut child = Command::new(program)
+        .args(args)
+        .current_dir(dir)
+        .env("PATH", path)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_AUTHOR_NAME", "test")
+        .env("GIT_AUTHOR_EMAIL", "test@example.com")
+        .env("GIT_COMMITTER_NAME", "test")
+        .env("GIT_COMMITTER_EMAIL", "test@example.com")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{} {:?}: {}",
+        program,
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+fn git(dir: &Path, args: &[&str]) -> Vec<u8> {
+    run(dir, "git", args, b"").stdout
+}
+
+// 创建已配置密钥的仓库，example.secret 由 init 写入的 .gitattributes 加密
+fn setup(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("git-cryptx-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    git(&dir, &["init", "-q"]);
+    run(&dir, "git-cryptx", &["init"], b"");
+    run(&dir, "git-cryptx", &["keygen"], b"");
+    fs::write(dir.join("example.secret"), "line1\nline2\nline3\n").unwrap();
+    git(&dir, &["add", "-A"]);
+    git(&dir, &["commit", "-q", "-m", "initial"]);
+    dir
+}
+
+#[test]
+fn test_clean_filter_reads_stdin() {
+    let dir = setup("clean-stdin");
+    fs::write(dir.join("example.secret"), "working tree\n").unwrap();
+
+    let encrypted = run(
+        &dir,
+        "git-cryptx",
+        &["clean", "example.secret"],
+        b"from stdin\n",
+    )
+    .stdout;
+    assert!(encrypted.starts_with(b"GITENC"));
+    let decrypted = run(
+        &dir,
+        "git-cryptx",
+        &["smudge", "example.secret"],
+        &encrypted,
+    )
+    .stdout;
+    assert_eq!(decrypted, b"from stdin\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_hash_object_path_encrypts_stdin() {
+    let dir = setup("hash-object");
+    fs::write(dir.join("example.secret"), "working tree\n").unwrap();
+
+    let output = run(
+        &dir,
+        "git",
+        &["hash-object", "-w", "--stdin", "--path", "example.secret"],
+        b"from stdin\n",
+    );
+    let object = String::from_utf8(output.stdout).unwrap();
+    let object = object.trim();
+    assert!(git(&dir, &["cat-file", "blob", object]).starts_with(b"GITENC"));
+    let decrypted = git(
+        &dir,
+        &["cat-file", "--filters", "--path=example.secret", object],
+    );
+    assert_eq!(decrypted, b"from stdin\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}